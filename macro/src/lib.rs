@@ -16,6 +16,16 @@ use syn::{AttributeArgs};
 struct MacroArgs {
     #[darling(default)]
     reversible: bool,
+    #[darling(default)]
+    propagate_unchanged: bool,
+    #[darling(default)]
+    family: bool,
+    #[darling(default)]
+    serialize: bool,
+    #[darling(default)]
+    persist: Option<String>,
+    #[darling(default)]
+    undo_scope: Option<String>,
 }
 
 #[derive(Debug, FromMeta)]
@@ -24,6 +34,10 @@ struct ReactionMacroArgs {
     existing_state: bool,
     #[darling(default)]
     suspended:bool,
+    #[darling(default)]
+    lazy: bool,
+    #[darling(default)]
+    propagate_unchanged: bool,
 }
 
 
@@ -159,14 +173,150 @@ pub fn atom(args: TokenStream, input: TokenStream) -> TokenStream {
         quote!( set_inert_atom_state_with_id::<#the_type>(value,__id );)
      };
 
+    // unless opted out, writes that leave the value unchanged do not propagate
+    // if the type implements PartialEq
+    let skip_unchanged = if args.propagate_unchanged {
+        quote!()
+    } else {
+        quote!(
+            if !reactive_state_exists_for_id::<#the_type>(__id) {
+                (&Probe::<#the_type>::new()).skip_unchanged(__id);
+            }
+        )
+    };
+
+    // values are rendered with Debug in the event log if the type implements it,
+    // checked on every access as the log may be enabled after the node is created
+    let log_debug = quote!(
+        (&Probe::<#the_type>::new()).log_debug(__id);
+    );
+
+    // the undo history estimates the values of reversible atoms with SizeHint
+    // if the type implements it
+    let hint_size = if args.reversible {
+        quote!(
+            if !reactive_state_exists_for_id::<#the_type>(__id) {
+                (&Probe::<#the_type>::new()).hint_size(__id);
+            }
+        )
+    } else {
+        quote!()
+    };
+
+    // the writes of an atom with an undo scope are recorded in the scope's
+    // history, including the initial one
+    let set_undo_scope = match &args.undo_scope {
+        Some(scope) => {
+            if !args.reversible {
+                panic!("Only a reversible atom can have an undo scope");
+            }
+            quote!(
+                if !reactive_state_exists_for_id::<#the_type>(__id) {
+                    set_undo_scope(__id, #scope);
+                }
+            )
+        }
+        None => quote!(),
+    };
+
+    // a serializable atom is made part of snapshots when it is created, the
+    // key is computed up front as the parameters are moved into the closure
+    let (snapshot_key, register_snapshot) = if args.serialize {
+        let path = format!("::{}", input_fn.sig.ident);
+        (
+            quote!(
+                let __snapshot_key = if reactive_state_exists_for_id::<#the_type>(__id) {
+                    None
+                } else {
+                    Some(snapshot_key(concat!(module_path!(), #path), &(#template_quote)))
+                };
+            ),
+            quote!(
+                if let Some(key) = __snapshot_key {
+                    serializable_atom::<#the_type>(__id, key);
+                }
+            ),
+        )
+    } else {
+        (quote!(), quote!())
+    };
+
+    // the writes of a serializable reversible atom can be saved with its undo
+    // history, from the initial one on
+    let serializable_undo_values = if args.serialize && args.reversible {
+        quote!(
+            if let Some(key) = &__snapshot_key {
+                serializable_undo_values::<#the_type>(__id, key.clone());
+            }
+        )
+    } else {
+        quote!()
+    };
+
+    // a persisted atom is saved under a single key, so it cannot be one of
+    // several atoms created from the same function
+    let create_atom = if let Some(key) = &args.persist {
+        if args.reversible {
+            panic!("A persisted atom cannot be reversible");
+        }
+        if args.family || !input_fn.sig.inputs.is_empty() {
+            panic!("A persisted atom cannot take parameters");
+        }
+        quote!(atom_persisted::<#the_type,_>(__id, #key, func))
+    } else {
+        quote!(#atom_fn_ident::<#the_type,_>(__id ,func))
+    };
+
+    // a family atom also generates `<name>_family()`, which tracks its members
+    // by parameter
+    let (family_fn, new_member, track_member) = if args.family {
+        if args.reversible {
+            panic!("A family atom cannot be reversible");
+        }
+        let arg_types = input_fn.sig.inputs.iter().map(get_arg_type).collect::<Vec<_>>();
+        let arg_names = input_fn.sig.inputs.iter().map(|input| format_ident!("{}",get_arg_name(input))).collect::<Vec<_>>();
+        let (param_type, param) = match arg_types.len() {
+            0 => panic!("A family atom needs at least one parameter"),
+            1 => (quote!(#(#arg_types)*), quote!(#(#arg_names.clone())*)),
+            _ => (quote!((#(#arg_types),*)), quote!((#(#arg_names.clone()),*))),
+        };
+        let family_ident = format_ident!("{}_family", input_fn.sig.ident);
+        (
+            quote!(
+                #vis fn #family_ident() -> AtomFamily<#param_type, #the_type> {
+                    atom_family::<#param_type, #the_type>(return_key_for_type_and_insert_if_required((CallSite::here(),)))
+                }
+            ),
+            quote!(
+                let __new_member = if reactive_state_exists_for_id::<#the_type>(__id) {
+                    None
+                } else {
+                    Some(#param)
+                };
+            ),
+            quote!(
+                if let Some(param) = __new_member {
+                    #family_ident().add_member(param, __id);
+                }
+            ),
+        )
+    } else {
+        (quote!(), quote!(), quote!())
+    };
+
 
     
     quote!(
 
+       #family_fn
+
        #vis #sig{
 
                 let __id  = return_key_for_type_and_insert_if_required(#hash_quote);
 
+                #new_member
+                #snapshot_key
+
                 let func = move || {
                     #use_args_quote
 
@@ -187,7 +337,15 @@ pub fn atom(args: TokenStream, input: TokenStream) -> TokenStream {
                     
                 };
 
-                #atom_fn_ident::<#the_type,_>(__id ,func)
+                #skip_unchanged
+                #log_debug
+                #hint_size
+                #set_undo_scope
+                #serializable_undo_values
+                let __atom = #create_atom;
+                #register_snapshot
+                #track_member
+                __atom
             
         } 
 
@@ -196,6 +354,13 @@ pub fn atom(args: TokenStream, input: TokenStream) -> TokenStream {
 }
 
 
+fn get_arg_type(fnarg : &FnArg) -> syn::Type {
+    match fnarg {
+            FnArg::Receiver(_) => panic!("cannot be a method with self receiver"),
+            FnArg::Typed(t) => (*t.ty).clone(),
+    }
+}
+
 fn get_arg_name(fnarg : &FnArg) -> String {
     match fnarg {
             FnArg::Receiver(_) => panic!("cannot be a method with self receiver"),
@@ -218,7 +383,13 @@ pub fn reaction(args: TokenStream, input: TokenStream) -> TokenStream {
         Err(e) => panic!("{}",e),
     };
 
-    let reaction_suspended_ident = if args.suspended {
+    if args.lazy && args.suspended {
+        panic!("A reaction cannot be both lazy and suspended, a lazy reaction only runs once it is read");
+    }
+
+    let reaction_suspended_ident = if args.lazy {
+        format_ident!("reaction_lazy")
+    } else if args.suspended {
         format_ident!("reaction_start_suspended")
     } else {
         format_ident!("reaction")
@@ -229,7 +400,20 @@ pub fn reaction(args: TokenStream, input: TokenStream) -> TokenStream {
     
 
     
-    let sig = input_fn.sig.clone();
+    // an async reaction returns its handle straight away, its future is run
+    // by `run_async_reaction`
+    let is_async = input_fn.sig.asyncness.is_some();
+    // async reactions and their `Loadable` need the single threaded store
+    if is_async && cfg!(feature = "sync") {
+        return syn::Error::new_spanned(
+            &input_fn.sig.asyncness,
+            "async #[reaction]s are not supported with the `sync` feature",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let mut sig = input_fn.sig.clone();
+    sig.asyncness = None;
     let vis = input_fn.vis.clone();
 
     let the_outer_type = match input_fn.sig.output.clone() {
@@ -268,6 +452,7 @@ pub fn reaction(args: TokenStream, input: TokenStream) -> TokenStream {
 
 
     let body = input_fn.block.clone();
+    let inputs = input_fn.sig.inputs.clone();
 
     let inputs_iter = &mut input_fn.sig.inputs.iter();
     let  mut inputs_iter_3 = inputs_iter.clone();
@@ -313,6 +498,31 @@ pub fn reaction(args: TokenStream, input: TokenStream) -> TokenStream {
     };
 
     
+    // unless opted out, recomputing the same value does not propagate if the
+    // type implements PartialEq
+    let skip_unchanged = if args.propagate_unchanged {
+        quote!()
+    } else {
+        quote!( (&Probe::<#the_type>::new()).skip_unchanged(__id); )
+    };
+
+    // the body of an async reaction becomes a future resolving to the
+    // `Result` its `Loadable` is made from, which unlinks the dependencies
+    // it no longer observes once it completes
+    let run_body = if is_async {
+        quote!(
+            async fn __async_reaction(#inputs) -> <#the_type as LoadableResult>::Result #body
+            run_async_reaction(__id, __async_reaction(#template_quote));
+        )
+    } else {
+        quote!(
+            let value = {#body};
+            set_inert_atom_state_with_id::<#the_type>(value,__id );
+            // we need to remove dependencies that do nto exist anymore
+            unlink_dead_links(__id );
+        )
+    };
+
     let quote = 
         quote!(
 
@@ -340,10 +550,7 @@ pub fn reaction(args: TokenStream, input: TokenStream) -> TokenStream {
                                 
                                 
                                 #use_existing_state
-                                let value = {#body};
-                                set_inert_atom_state_with_id::<#the_type>(value,__id );
-                                // we need to remove dependencies that do nto exist anymore
-                                unlink_dead_links(__id );
+                                #run_body
                             })
                             
                         }
@@ -356,6 +563,13 @@ pub fn reaction(args: TokenStream, input: TokenStream) -> TokenStream {
                         };
                         
 
+                        // a lazy or suspended reaction has no value until it first runs,
+                        // so it is only new if it has not been registered either
+                        // values are rendered with Debug in the event log if the type implements it
+                        (&Probe::<#the_type>::new()).log_debug(__id);
+                        if !reactive_state_exists_for_id::<RxFunc>(__id) {
+                            #skip_unchanged
+                        }
                         #reaction_suspended_ident::<#the_type,_>(__id ,func)
                     } else {
                         Reaction::<#the_type>::new(__id )                 
@@ -1,13 +1,15 @@
-#[derive(Clone)]
+use atomic_hooks::{reaction::Reaction, reversible_atom::ReversibleAtom, *};
+
+#[derive(Clone, PartialEq)]
 struct Pos(f64, f64);
 
 #[atom(reversible)]
-fn a_pos() -> AtomReversible<Pos> {
+fn a_pos() -> ReversibleAtom<Pos> {
     Pos(0., 0.)
 }
 
 #[atom(reversible)]
-fn b_pos() -> AtomReversible<Pos> {
+fn b_pos() -> ReversibleAtom<Pos> {
     Pos(0., 0.)
 }
 
@@ -18,23 +20,32 @@ fn a_b_distance() -> Reaction<f64> {
     ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
 }
 
-#[test]
-fn test_atome_reversible() {
+fn distance() -> f64 {
+    a_b_distance().get_with(|distance| *distance)
+}
+
+fn main() {
     let a_pos = a_pos();
     let b_pos = b_pos();
-    let a_b_distance = a_b_distance();
+    a_b_distance();
 
-    println!("A is at : {}", a_pos.get());
-    println!("B is at : {}", b_pos.get());
-    println!("The distance between them is : {}", a_b_distance.get());
-    assert!((a_b_distance.get() - 0.0).abs() < std::f64::EPSILON);
+    println!("A is at : {}", a_pos.get_with(|pos| pos.to_string()));
+    println!("B is at : {}", b_pos.get_with(|pos| pos.to_string()));
+    println!("The distance between them is : {}", distance());
+    assert!((distance() - 0.0).abs() < std::f64::EPSILON);
 
     a_pos.update(|s| *s = Pos(4., 5.));
     b_pos.update(|s| *s = Pos(1., 1.));
-    println!("moving a to {} and b to {}", a_pos.get(), b_pos.get());
-    println!("The distance between them is now : {}", a_b_distance.get());
+    println!("The distance between them is now : {}", distance());
+    assert!((distance() - 5.0).abs() < std::f64::EPSILON);
+
+    global_reverse_queue().travel_backwards();
+    println!("After undoing the move of b it is : {}", distance());
+    assert!((distance() - 41f64.sqrt()).abs() < std::f64::EPSILON);
 
-    assert!((a_b_distance.get() - 5.0).abs() < std::f64::EPSILON);
+    global_reverse_queue().travel_forwards();
+    println!("After redoing it, it is back to : {}", distance());
+    assert!((distance() - 5.0).abs() < std::f64::EPSILON);
 }
 
 impl std::fmt::Display for Pos {
@@ -0,0 +1,123 @@
+use crate::store::StorageKey;
+
+/// A node taking part in a dependency cycle, together with the source
+/// location of the `#[atom]` or `#[reaction]` that created it, if known.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CycleNode {
+    pub id: StorageKey,
+    pub location: Option<String>,
+}
+
+impl std::fmt::Display for CycleNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "{}", location),
+            None => write!(f, "{:?}", self.id),
+        }
+    }
+}
+
+/// Reported when a reaction would (transitively) observe itself.
+///
+/// The path starts and ends with the same node, each step pointing from an
+/// observed node to the node observing it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DependencyCycle {
+    pub path: Vec<CycleNode>,
+}
+
+impl std::fmt::Display for DependencyCycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "dependency cycle detected: ")?;
+        for (idx, node) in self.path.iter().enumerate() {
+            if idx > 0 {
+                write!(f, " -> ")?;
+            }
+            write!(f, "{}", node)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DependencyCycle {}
+
+/// The ways in which accessing stored state can fail.
+///
+/// Returned by the `try_*` family of accessor methods, which mirror the
+/// panicking methods of the same name without the prefix.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AtomicHooksError {
+    /// No state of the requested type is stored for the id.
+    Missing { id: StorageKey },
+    /// The id holds state of a different type than the one requested.
+    WrongType {
+        id: StorageKey,
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// The state was observed outside of a `#[reaction]`.
+    NoReactiveContext,
+    /// The state is already being read or updated further up the stack, e.g.
+    /// from inside the closure passed to its own `get_with` or `update`.
+    ReentrantBorrow { id: StorageKey },
+    /// Observing the state would make a reaction depend on itself.
+    Cycle(DependencyCycle),
+    /// An async reaction is pending but no executor is set to drive it, see
+    /// `set_executor`.
+    NoExecutor { id: StorageKey },
+    /// The value of the atom snapshot under the key cannot be serialized, e.g.
+    /// because it is a map whose keys are not strings.
+    Serialize { key: String, message: String },
+}
+
+impl std::fmt::Display for AtomicHooksError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AtomicHooksError::Missing { id } => write!(f, "no state is stored for {:?}", id),
+            AtomicHooksError::WrongType {
+                id,
+                expected,
+                found,
+            } => write!(
+                f,
+                "state for {:?} is of type {}, not {}",
+                id, found, expected
+            ),
+            AtomicHooksError::NoReactiveContext => write!(
+                f,
+                "No #[reaction] context found, are you sure you are in one? I.e. does the current \
+                 function have a #[reaction] tag?"
+            ),
+            AtomicHooksError::ReentrantBorrow { id } => write!(
+                f,
+                "state for {:?} is already being read or updated further up the stack",
+                id
+            ),
+            AtomicHooksError::Cycle(cycle) => write!(f, "{}", cycle),
+            AtomicHooksError::NoExecutor { id } => write!(
+                f,
+                "the async reaction {:?} is pending but no executor is set, see `set_executor`",
+                id
+            ),
+            AtomicHooksError::Serialize { key, message } => {
+                write!(f, "atom {} cannot be serialized: {}", key, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AtomicHooksError {}
+
+impl From<DependencyCycle> for AtomicHooksError {
+    fn from(cycle: DependencyCycle) -> Self {
+        AtomicHooksError::Cycle(cycle)
+    }
+}
+
+/// Lets async reactions failing with a `String` report errors of the store,
+/// e.g. a missing executor.
+impl From<AtomicHooksError> for String {
+    fn from(error: AtomicHooksError) -> String {
+        error.to_string()
+    }
+}
@@ -0,0 +1,412 @@
+use crate::{
+    graph::NodeKind,
+    probe::probe,
+    reactive_state_functions::{set_inert_atom_state_with_id, with_store},
+    store::{Storable, StorageKey},
+};
+use std::{collections::VecDeque, time::Instant};
+
+/// A mutation of a store, as recorded in its event log.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StoreEvent {
+    /// An atom, reaction or subscription was created.
+    Created { id: StorageKey, kind: NodeKind },
+    /// The value of a node was set or updated. The values are rendered with
+    /// `Debug` for atoms and reactions whose type implements it.
+    Set {
+        id: StorageKey,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// A reaction was re-run because something it observes changed.
+    ReactionRan { id: StorageKey },
+    /// `dependent` started observing `source`.
+    DependencyAdded {
+        source: StorageKey,
+        dependent: StorageKey,
+    },
+    /// `dependent` stopped observing `source`.
+    DependencyRemoved {
+        source: StorageKey,
+        dependent: StorageKey,
+    },
+    /// The value of a node was removed.
+    Removed { id: StorageKey },
+    /// A node was disposed of together with its edges.
+    Disposed { id: StorageKey },
+    /// The persist backend failed to save a write of a persisted atom.
+    PersistFailed {
+        id: StorageKey,
+        key: String,
+        error: String,
+    },
+}
+
+impl StoreEvent {
+    /// Whether the event concerns the node, including either end of an edge.
+    pub fn involves(&self, node: StorageKey) -> bool {
+        match self {
+            StoreEvent::Created { id, .. }
+            | StoreEvent::Set { id, .. }
+            | StoreEvent::ReactionRan { id }
+            | StoreEvent::Removed { id }
+            | StoreEvent::Disposed { id }
+            | StoreEvent::PersistFailed { id, .. } => *id == node,
+            StoreEvent::DependencyAdded { source, dependent }
+            | StoreEvent::DependencyRemoved { source, dependent } => {
+                *source == node || *dependent == node
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LoggedEvent {
+    /// Position of the event in the store's history, counting from 0 when the
+    /// log was enabled. Not reset by `clear_event_log`.
+    pub sequence: u64,
+    pub at: Instant,
+    pub event: StoreEvent,
+}
+
+/// The most recent events of a store, oldest first.
+pub(crate) struct EventLog {
+    capacity: usize,
+    next_sequence: u64,
+    events: VecDeque<LoggedEvent>,
+}
+
+impl EventLog {
+    pub(crate) fn new(capacity: usize) -> EventLog {
+        EventLog {
+            capacity,
+            next_sequence: 0,
+            events: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub(crate) fn push(&mut self, event: StoreEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(LoggedEvent {
+            sequence: self.next_sequence,
+            at: Instant::now(),
+            event,
+        });
+        self.next_sequence += 1;
+    }
+
+    pub(crate) fn events(&self) -> impl Iterator<Item = &LoggedEvent> {
+        self.events.iter()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.events.clear();
+    }
+}
+
+/// Renders the value of a node for the event log.
+pub struct DebugRender<T> {
+    pub(crate) render: fn(&T) -> String,
+}
+
+impl<T> Clone for DebugRender<T> {
+    fn clone(&self) -> DebugRender<T> {
+        *self
+    }
+}
+
+impl<T> Copy for DebugRender<T> {}
+
+/// Starts recording the events of the current store, keeping the most recent
+/// `capacity` of them. Events recorded earlier are discarded.
+///
+/// ```
+/// use atomic_hooks::{atom::Atom, *};
+///
+/// #[atom]
+/// fn count() -> Atom<i32> {
+///     0
+/// }
+///
+/// count();
+/// enable_event_log(100);
+/// count().set(1);
+///
+/// let events = event_log_for(count().id);
+/// assert_eq!(
+///     events[0].event,
+///     StoreEvent::Set {
+///         id: count().id,
+///         old: Some("0".to_string()),
+///         new: Some("1".to_string()),
+///     }
+/// );
+/// ```
+pub fn enable_event_log(capacity: usize) {
+    with_store(|store_refcell| store_refcell.borrow_mut().enable_event_log(capacity))
+}
+
+/// Stops recording events and discards the recorded ones.
+pub fn disable_event_log() {
+    with_store(|store_refcell| store_refcell.borrow_mut().disable_event_log())
+}
+
+/// The recorded events of the current store, oldest first.
+pub fn event_log() -> Vec<LoggedEvent> {
+    logged_events(|_| true)
+}
+
+/// The recorded events concerning the node, oldest first.
+pub fn event_log_for(id: StorageKey) -> Vec<LoggedEvent> {
+    logged_events(|logged| logged.event.involves(id))
+}
+
+/// The events recorded after the one with the given sequence number.
+pub fn event_log_since(sequence: u64) -> Vec<LoggedEvent> {
+    logged_events(|logged| logged.sequence > sequence)
+}
+
+/// Discards the recorded events, recording continues.
+pub fn clear_event_log() {
+    with_store(|store_refcell| store_refcell.borrow_mut().clear_event_log())
+}
+
+fn logged_events<F: Fn(&LoggedEvent) -> bool>(filter: F) -> Vec<LoggedEvent> {
+    with_store(|store_refcell| {
+        store_refcell
+            .borrow()
+            .logged_events()
+            .filter(|logged| filter(logged))
+            .cloned()
+            .collect()
+    })
+}
+
+/// Renders the values of the node with `Debug` in the event log.
+pub fn log_values_with_debug<T: Storable + std::fmt::Debug>(id: StorageKey) {
+    set_inert_atom_state_with_id(
+        DebugRender::<T> {
+            render: |value| format!("{:?}", value),
+        },
+        id,
+    )
+}
+
+// `#[atom]` and `#[reaction]` call `log_debug` on every access, as the log
+// may be enabled after the node was created. The renderer is only recorded
+// while the event log is enabled.
+fn log_debug_while_enabled<T: Storable + std::fmt::Debug>(id: StorageKey) {
+    let unrecorded = with_store(|store_refcell| {
+        let store = store_refcell.borrow();
+        store.event_log_enabled() && store.get_state_with_id::<DebugRender<T>>(&id).is_none()
+    });
+    if unrecorded {
+        log_values_with_debug::<T>(id)
+    }
+}
+
+probe!(
+    LogDebug,
+    LogWithoutDebug,
+    log_debug,
+    [Storable + std::fmt::Debug],
+    log_debug_while_enabled
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        reactive_state_access::{atom::Atom, reaction::Reaction},
+        *,
+    };
+
+    // deliberately not Debug
+    #[derive(Clone, PartialEq)]
+    struct Opaque(u32);
+
+    #[atom]
+    fn count() -> Atom<i32> {
+        0
+    }
+
+    #[atom]
+    fn secret() -> Atom<Opaque> {
+        Opaque(0)
+    }
+
+    #[reaction]
+    fn doubled() -> Reaction<i32> {
+        count().observe() * 2
+    }
+
+    #[reaction]
+    fn switch() -> Reaction<i32> {
+        if count().observe() > 0 {
+            doubled().observe()
+        } else {
+            0
+        }
+    }
+
+    fn events_for(id: StorageKey) -> Vec<StoreEvent> {
+        event_log_for(id)
+            .into_iter()
+            .map(|logged| logged.event)
+            .collect()
+    }
+
+    #[test]
+    fn test_log_is_opt_in() {
+        count().set(1);
+        assert!(event_log().is_empty());
+
+        enable_event_log(10);
+        count().set(2);
+        assert_eq!(event_log().len(), 1);
+
+        disable_event_log();
+        count().set(3);
+        assert!(event_log().is_empty());
+    }
+
+    #[test]
+    fn test_log_records_mutations() {
+        enable_event_log(100);
+        let doubled = doubled();
+        count().update(|c| *c += 1);
+        let count_id = count().id;
+
+        assert_eq!(
+            events_for(doubled.id),
+            vec![
+                StoreEvent::Created {
+                    id: doubled.id,
+                    kind: NodeKind::Reaction
+                },
+                StoreEvent::DependencyAdded {
+                    source: count_id,
+                    dependent: doubled.id
+                },
+                StoreEvent::Set {
+                    id: doubled.id,
+                    old: None,
+                    new: Some("0".to_string())
+                },
+                StoreEvent::ReactionRan { id: doubled.id },
+                StoreEvent::Set {
+                    id: doubled.id,
+                    old: Some("0".to_string()),
+                    new: Some("2".to_string())
+                },
+            ]
+        );
+        assert!(events_for(count_id).contains(&StoreEvent::Set {
+            id: count_id,
+            old: Some("0".to_string()),
+            new: Some("1".to_string())
+        }));
+
+        doubled.remove();
+        assert_eq!(
+            events_for(doubled.id).last(),
+            Some(&StoreEvent::Removed { id: doubled.id })
+        );
+    }
+
+    #[test]
+    fn test_log_records_unlinked_dependencies() {
+        count().set(1);
+        let switch = switch();
+        enable_event_log(100);
+
+        count().set(0);
+        assert!(
+            events_for(switch.id).contains(&StoreEvent::DependencyRemoved {
+                source: doubled().id,
+                dependent: switch.id
+            })
+        );
+    }
+
+    #[test]
+    fn test_renderers_are_only_recorded_while_logging() {
+        let id = count().id;
+        let recorded = || {
+            with_store(|store_refcell| {
+                store_refcell
+                    .borrow()
+                    .get_state_with_id::<DebugRender<i32>>(&id)
+                    .is_some()
+            })
+        };
+        count().set(1);
+        assert!(!recorded());
+
+        enable_event_log(10);
+        count().set(2);
+        assert!(recorded());
+        assert_eq!(
+            events_for(id),
+            vec![StoreEvent::Set {
+                id,
+                old: Some("1".to_string()),
+                new: Some("2".to_string())
+            }]
+        );
+    }
+
+    #[test]
+    fn test_values_without_debug_are_not_rendered() {
+        enable_event_log(10);
+        secret().set(Opaque(1));
+
+        assert_eq!(
+            events_for(secret().id),
+            vec![
+                StoreEvent::Created {
+                    id: secret().id,
+                    kind: NodeKind::Atom
+                },
+                StoreEvent::Set {
+                    id: secret().id,
+                    old: None,
+                    new: None
+                },
+                StoreEvent::Set {
+                    id: secret().id,
+                    old: None,
+                    new: None
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_log_is_bounded_and_ordered() {
+        count();
+        enable_event_log(3);
+        for value in 1..=5 {
+            count().set(value);
+        }
+
+        let events = event_log();
+        let sequences = events
+            .iter()
+            .map(|logged| logged.sequence)
+            .collect::<Vec<_>>();
+        assert_eq!(sequences, vec![2, 3, 4]);
+        assert!(events.windows(2).all(|pair| pair[0].at <= pair[1].at));
+        assert_eq!(event_log_since(3).len(), 1);
+
+        clear_event_log();
+        assert!(event_log().is_empty());
+        count().set(6);
+        assert_eq!(event_log()[0].sequence, 5);
+    }
+}
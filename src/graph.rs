@@ -0,0 +1,277 @@
+use crate::{reactive_state_functions::with_store, store::StorageKey};
+use std::fmt::Write;
+
+/// What created a node of the dependency graph.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    Atom,
+    Reaction,
+    Subscription,
+    HookState,
+}
+
+impl NodeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            NodeKind::Atom => "atom",
+            NodeKind::Reaction => "reaction",
+            NodeKind::Subscription => "subscription",
+            NodeKind::HookState => "hook state",
+        }
+    }
+
+    fn dot_shape(self) -> &'static str {
+        match self {
+            NodeKind::Atom => "box",
+            NodeKind::Reaction => "ellipse",
+            NodeKind::Subscription => "diamond",
+            NodeKind::HookState => "note",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GraphNode {
+    pub id: StorageKey,
+    pub kind: NodeKind,
+    /// Type of the node's value, if it was recorded when the node was created.
+    pub value_type: Option<&'static str>,
+    /// Source location of the code that created the node, if known.
+    pub location: Option<String>,
+}
+
+/// A snapshot of the nodes in a store and of the edges between them.
+///
+/// Each edge `(from, to)` holds indices into `nodes` and points from an
+/// observed node to the node observing it, i.e. in the direction changes
+/// propagate. Nodes are ordered by location and type, so that exports of the
+/// same program can be diffed.
+/// ```
+/// use atomic_hooks::{atom::Atom, reaction::Reaction, *};
+///
+/// #[atom]
+/// fn count() -> Atom<i32> {
+///     0
+/// }
+///
+/// #[reaction]
+/// fn doubled() -> Reaction<i32> {
+///     count().observe() * 2
+/// }
+///
+/// doubled();
+/// let graph = dependency_graph();
+/// assert_eq!(graph.edges.len(), 1);
+/// println!("{}", graph.to_dot());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DependencyGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<(usize, usize)>,
+}
+
+impl DependencyGraph {
+    /// Renders the graph in Graphviz DOT, e.g. for `dot -Tsvg`.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph atomic_hooks {\n");
+        for (idx, node) in self.nodes.iter().enumerate() {
+            let mut label = node.kind.name().to_string();
+            for line in node
+                .value_type
+                .iter()
+                .copied()
+                .chain(node.location.as_deref())
+            {
+                label.push('\n');
+                label.push_str(line);
+            }
+            writeln!(
+                dot,
+                "    n{} [label=\"{}\", shape={}];",
+                idx,
+                escape(&label),
+                node.kind.dot_shape()
+            )
+            .unwrap();
+        }
+        for (from, to) in &self.edges {
+            writeln!(dot, "    n{} -> n{};", from, to).unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Renders the graph as a JSON document of the form
+    /// `{"nodes": [{"id", "kind", "type", "location"}], "edges": [{"from", "to"}]}`,
+    /// with node ids being their indices and missing fields being `null`.
+    pub fn to_json(&self) -> String {
+        let optional = |value: Option<&str>| match value {
+            Some(value) => format!("\"{}\"", escape(value)),
+            None => "null".to_string(),
+        };
+
+        let mut json = String::from("{\"nodes\":[");
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if idx > 0 {
+                json.push(',');
+            }
+            write!(
+                json,
+                "{{\"id\":{},\"kind\":\"{}\",\"type\":{},\"location\":{}}}",
+                idx,
+                node.kind.name(),
+                optional(node.value_type),
+                optional(node.location.as_deref())
+            )
+            .unwrap();
+        }
+        json.push_str("],\"edges\":[");
+        for (idx, (from, to)) in self.edges.iter().enumerate() {
+            if idx > 0 {
+                json.push(',');
+            }
+            write!(json, "{{\"from\":{},\"to\":{}}}", from, to).unwrap();
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+// escapes a string for use inside double quotes, in both DOT and JSON
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Exports the dependency graph of the current store.
+pub fn dependency_graph() -> DependencyGraph {
+    with_store(|store_refcell| store_refcell.borrow().dependency_graph())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        reactive_state_access::{atom::Atom, reaction::Reaction},
+        *,
+    };
+
+    #[atom]
+    fn width() -> Atom<u32> {
+        2
+    }
+
+    #[atom]
+    fn height() -> Atom<u32> {
+        3
+    }
+
+    #[reaction]
+    fn area() -> Reaction<u32> {
+        width().observe() * height().observe()
+    }
+
+    fn node_index(graph: &DependencyGraph, kind: NodeKind, value_type: &str) -> Vec<usize> {
+        graph
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.kind == kind && node.value_type == Some(value_type))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    #[test]
+    fn test_graph_nodes_and_edges() {
+        let runtime = Runtime::new();
+        let graph = runtime.enter(|| {
+            area();
+            dependency_graph()
+        });
+
+        let atoms = node_index(&graph, NodeKind::Atom, "u32");
+        let reactions = node_index(&graph, NodeKind::Reaction, "u32");
+        assert_eq!(atoms.len(), 2);
+        assert_eq!(reactions.len(), 1);
+        assert!(graph.nodes[reactions[0]]
+            .location
+            .as_ref()
+            .unwrap()
+            .contains("graph.rs"));
+
+        let mut edges = graph.edges.clone();
+        edges.sort();
+        assert_eq!(
+            edges,
+            vec![(atoms[0], reactions[0]), (atoms[1], reactions[0])]
+        );
+    }
+
+    #[test]
+    fn test_graph_shows_subscriptions_and_hook_states() {
+        let runtime = Runtime::new();
+        let graph = runtime.enter(|| {
+            let _subscription = width().subscribe(|_, _| {});
+            use_state(|| "local".to_string());
+            dependency_graph()
+        });
+
+        let atom = node_index(&graph, NodeKind::Atom, "u32")[0];
+        let subscription = node_index(&graph, NodeKind::Subscription, "u32")[0];
+        assert_eq!(graph.edges, vec![(atom, subscription)]);
+        assert_eq!(
+            node_index(&graph, NodeKind::HookState, "alloc::string::String").len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_graph_export_formats() {
+        let graph = DependencyGraph {
+            nodes: vec![
+                GraphNode {
+                    id: StorageKey::SlottedKey(store::SlottedKey {
+                        location: 1,
+                        slot: 0,
+                        generation: 0,
+                    }),
+                    kind: NodeKind::Atom,
+                    value_type: Some("u32"),
+                    location: Some("src/\"quoted\".rs:1:1".to_string()),
+                },
+                GraphNode {
+                    id: StorageKey::SlottedKey(store::SlottedKey {
+                        location: 2,
+                        slot: 0,
+                        generation: 0,
+                    }),
+                    kind: NodeKind::Reaction,
+                    value_type: None,
+                    location: None,
+                },
+            ],
+            edges: vec![(0, 1)],
+        };
+
+        assert_eq!(
+            graph.to_dot(),
+            "digraph atomic_hooks {\n    n0 [label=\"atom\\nu32\\nsrc/\\\"quoted\\\".rs:1:1\", \
+             shape=box];\n    n1 [label=\"reaction\", shape=ellipse];\n    n0 -> n1;\n}\n"
+        );
+        assert_eq!(
+            graph.to_json(),
+            "{\"nodes\":[{\"id\":0,\"kind\":\"atom\",\"type\":\"u32\",\"location\":\"src/\\\"\
+             quoted\\\".rs:1:1\"},{\"id\":1,\"kind\":\"reaction\",\"type\":null,\"location\":\
+             null}],\"edges\":[{\"from\":0,\"to\":1}]}"
+        );
+    }
+}
@@ -1,7 +1,11 @@
 use crate::reactive_state_access::state_access::{CloneState, StateAccess};
 use crate::{
-    reactive_state_functions::{execute_reaction_nodes, STORE},
-    store::{ReactiveContext, SlottedKey, StorageKey, TopoKey},
+    error::AtomicHooksError,
+    reactive_state_functions::{
+        execute_reaction_nodes, remove_reactive_state_with_id, with_checked_out, with_store,
+        with_store_lock,
+    },
+    store::{ReactiveContext, SlottedKey, Storable, StorableFn, StorageKey, TopoKey},
     unmount::Unmount,
 };
 use std::{cell::RefCell, collections::HashSet};
@@ -36,46 +40,70 @@ use std::{cell::RefCell, collections::HashSet};
 /// to be read via their accessor in a more restrictive way.
 // in a parent context.
 #[topo::nested]
-pub fn use_state<T: 'static, F: FnOnce() -> T>(data_fn: F) -> StateAccess<T> {
+pub fn use_state<T: Storable, F: FnOnce() -> T>(data_fn: F) -> StateAccess<T> {
     use_state_current(data_fn)
 }
 
 ///
 ///  Uses the current topological id to create a new state accessor
 ///
-pub fn use_state_current<T: 'static, F: FnOnce() -> T>(data_fn: F) -> StateAccess<T> {
-    let id = topo::CallId::current();
-    let ctx = get_state_slotted_key_struct_if_in_context();
+pub fn use_state_current<T: Storable, F: FnOnce() -> T>(data_fn: F) -> StateAccess<T> {
+    with_store_lock(|| {
+        let id = topo::CallId::current();
+        let ctx = get_state_slotted_key_struct_if_in_context();
 
-    let id = TopoKey { ctx, id };
+        let id = TopoKey { ctx, id };
 
-    if !state_exists_for_topo_id::<T>(id) {
-        set_state_with_topo_id::<T>(data_fn(), id);
-    }
-    // mark_id_as_active(current_id);
-    StateAccess::new(id)
+        if !state_exists_for_topo_id::<T>(id) {
+            with_store(|store_refcell| {
+                store_refcell
+                    .borrow_mut()
+                    .record_value_type::<T>(&StorageKey::TopoKey(id))
+            });
+            set_state_with_topo_id::<T>(data_fn(), id);
+        }
+        // mark_id_as_active(current_id);
+        StateAccess::new(id)
+    })
 }
 
 #[topo::nested]
-pub fn new_state<T: 'static, F: FnOnce() -> T>(data_fn: F) -> StateAccess<T> {
+pub fn new_state<T: Storable, F: FnOnce() -> T>(data_fn: F) -> StateAccess<T> {
     let count = use_state(|| 0);
     count.update(|c| *c += 1);
     topo::call_in_slot(&count.get(), || use_state_current(data_fn))
 }
 
 /// Sets the state of type T keyed to the given TopoId
-pub fn set_state_with_topo_id<T: 'static>(data: T, current_id: TopoKey) {
-    STORE.with(|store_refcell| {
+pub fn set_state_with_topo_id<T: Storable>(data: T, current_id: TopoKey) {
+    with_store(|store_refcell| {
         store_refcell
             .borrow_mut()
-            .set_state_with_id::<T>(data, &StorageKey::TopoKey(current_id))
+            .set_logged_state_with_id::<T>(data, &StorageKey::TopoKey(current_id))
     });
 
     execute_reaction_nodes(&StorageKey::TopoKey(current_id));
 }
 
-pub fn state_exists_for_topo_id<T: 'static>(id: TopoKey) -> bool {
-    STORE.with(|store_refcell| {
+/// Sets the state of type T keyed to the given TopoId, failing if the id
+/// holds state of another type or is being read or updated.
+pub fn try_set_state_with_topo_id<T: Storable>(
+    data: T,
+    current_id: TopoKey,
+) -> Result<(), AtomicHooksError> {
+    with_store_lock(|| {
+        with_store(|store_refcell| {
+            store_refcell
+                .borrow()
+                .check_writable::<T>(&StorageKey::TopoKey(current_id))
+        })?;
+        set_state_with_topo_id(data, current_id);
+        Ok(())
+    })
+}
+
+pub fn state_exists_for_topo_id<T: Storable>(id: TopoKey) -> bool {
+    with_store(|store_refcell| {
         store_refcell
             .borrow()
             .state_exists_with_id::<T>(StorageKey::TopoKey(id))
@@ -96,8 +124,8 @@ fn get_state_slotted_key_struct_if_in_context() -> Option<SlottedKey> {
 }
 
 /// Clones the state of type T keyed to the given TopoId
-pub fn clone_state_with_topo_id<T: 'static + Clone>(id: TopoKey) -> Option<T> {
-    STORE.with(|store_refcell| {
+pub fn clone_state_with_topo_id<T: Storable + Clone>(id: TopoKey) -> Option<T> {
+    with_store(|store_refcell| {
         store_refcell
             .borrow_mut()
             .get_state_with_id::<T>(&StorageKey::TopoKey(id))
@@ -105,14 +133,23 @@ pub fn clone_state_with_topo_id<T: 'static + Clone>(id: TopoKey) -> Option<T> {
     })
 }
 
-pub fn remove_state_with_topo_id<T: 'static>(id: TopoKey) -> Option<T> {
-    STORE.with(|store_refcell| {
-        store_refcell
-            .borrow_mut()
-            .remove_state_with_id::<T>(&StorageKey::TopoKey(id))
+pub fn try_clone_state_with_topo_id<T: Storable + Clone>(
+    id: TopoKey,
+) -> Result<T, AtomicHooksError> {
+    let id = StorageKey::TopoKey(id);
+    with_store(|store_refcell| {
+        let store = store_refcell.borrow();
+        store
+            .get_state_with_id::<T>(&id)
+            .cloned()
+            .ok_or_else(|| store.missing_state_error::<T>(&id))
     })
 }
 
+pub fn remove_state_with_topo_id<T: Storable>(id: TopoKey) -> Option<T> {
+    remove_reactive_state_with_id::<T>(StorageKey::TopoKey(id))
+}
+
 /// Provides mutable access to the stored state type T.
 ///
 /// Example:
@@ -121,21 +158,46 @@ pub fn remove_state_with_topo_id<T: 'static>(id: TopoKey) -> Option<T> {
 /// update_state_with_topo_id::<Vec<String>>( topo::CallId::current(), |v|
 ///     v.push("foo".to_string()
 /// )
-pub fn update_state_with_topo_id<T: 'static, F: FnOnce(&mut T) -> ()>(id: TopoKey, func: F) {
-    let mut item = remove_state_with_topo_id::<T>(id)
-        .expect("You are trying to update a type state that doesnt exist in this context!");
-    func(&mut item);
-    set_state_with_topo_id(item, id);
+pub fn update_state_with_topo_id<T: Storable, F: FnOnce(&mut T) -> ()>(id: TopoKey, func: F) {
+    try_update_state_with_topo_id(id, func).unwrap_or_else(|err| {
+        panic!(
+            "You are trying to update a type state that doesnt exist in this context! ({})",
+            err
+        )
+    })
+}
+
+pub fn try_update_state_with_topo_id<T: Storable, F: FnOnce(&mut T)>(
+    id: TopoKey,
+    func: F,
+) -> Result<(), AtomicHooksError> {
+    with_store_lock(|| {
+        with_checked_out(StorageKey::TopoKey(id), func)?;
+
+        execute_reaction_nodes(&StorageKey::TopoKey(id));
+        Ok(())
+    })
+}
 
-    execute_reaction_nodes(&StorageKey::TopoKey(id));
+pub fn read_state_with_topo_id<T: Storable, F: FnOnce(&T) -> R, R>(id: TopoKey, func: F) -> R {
+    try_read_state_with_topo_id(id, func).unwrap_or_else(|err| {
+        panic!(
+            "You are trying to read a type state that doesnt exist in this context! ({})",
+            err
+        )
+    })
 }
 
-pub fn read_state_with_topo_id<T: 'static, F: FnOnce(&T) -> R, R>(id: TopoKey, func: F) -> R {
-    let item = remove_state_with_topo_id::<T>(id)
-        .expect("You are trying to read a type state that doesnt exist in this context!");
-    let read = func(&item);
-    set_state_with_topo_id(item, id);
-    read
+pub fn try_read_state_with_topo_id<T: Storable, F: FnOnce(&T) -> R, R>(
+    id: TopoKey,
+    func: F,
+) -> Result<R, AtomicHooksError> {
+    with_store_lock(|| {
+        let read = with_checked_out(StorageKey::TopoKey(id), |item: &mut T| func(item))?;
+
+        execute_reaction_nodes(&StorageKey::TopoKey(id));
+        Ok(read)
+    })
 }
 
 /// Rudamentary Garbage Collection
@@ -156,7 +218,7 @@ pub fn read_state_with_topo_id<T: 'static, F: FnOnce(&T) -> R, R>(id: TopoKey, f
 /// accessed
 
 pub fn reset_unseen_id_list() {
-    STORE.with(|store_refcell| {
+    with_store(|store_refcell| {
         let mut store_mut = store_refcell.borrow_mut();
 
         store_mut.unseen_ids = HashSet::new();
@@ -177,7 +239,7 @@ pub fn reset_unseen_id_list() {
 }
 
 pub fn unseen_ids() -> Vec<TopoKey> {
-    STORE.with(|store_refcell| {
+    with_store(|store_refcell| {
         let store_mut = store_refcell.borrow_mut();
         store_mut.unseen_ids.iter().cloned().collect::<Vec<_>>()
     })
@@ -193,6 +255,6 @@ pub fn execute_and_remove_unmounts() {
 }
 
 #[topo::nested]
-pub fn on_unmount<F: Fn() -> () + 'static>(unmount_fn: F) -> StateAccess<Unmount> {
+pub fn on_unmount<F: StorableFn>(unmount_fn: F) -> StateAccess<Unmount> {
     use_state(|| Unmount::new(unmount_fn))
 }
@@ -1,18 +1,35 @@
 pub use atomic_hooks_macros::{atom, reaction};
 // storage
+mod runtime;
 pub mod store;
 
+// errors
+pub mod error;
+
+// introspection
+pub mod event_log;
+pub mod graph;
+
 // hooks
 mod hooks_state_functions;
 
 // reactive state
 
+// async reactions need the single threaded store, `#[reaction]` rejects them
+// with `sync`
+#[cfg(not(feature = "sync"))]
+pub mod loadable;
 mod marker;
+#[cfg(feature = "serde")]
+pub mod persist;
 mod reactive_state_access;
 pub mod reactive_state_functions;
+#[cfg(feature = "serde")]
+pub mod snapshot;
 
 // helpers
 mod helpers;
+mod probe;
 // mod seed_integration;
 pub mod reverse;
 
@@ -0,0 +1,538 @@
+use crate::{
+    error::AtomicHooksError,
+    reactive_state_functions::{
+        set_atom_state_with_id, set_inert_atom_state_with_id, unlink_dead_links, with_store,
+    },
+    runtime::Runtime,
+    store::{ReactiveContext, Storable, StorageKey},
+};
+use std::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Wake, Waker},
+};
+
+/// The value of an async `#[reaction]`.
+///
+/// The reaction is `Loading` while its future is pending, which includes the
+/// time spent refreshing after one of its dependencies changed. The future
+/// resolves to a `Result`, which becomes `Ready` or `Failed`.
+/// ```
+/// use atomic_hooks::{atom::Atom, reaction::Reaction, *};
+///
+/// #[atom]
+/// fn user_id() -> Atom<u32> {
+///     1
+/// }
+///
+/// #[reaction]
+/// async fn user_name() -> Reaction<Loadable<String>> {
+///     let id = user_id().observe();
+///     if id == 0 {
+///         return Err("no such user".to_string());
+///     }
+///     Ok(format!("user {}", id))
+/// }
+///
+/// assert_eq!(
+///     user_name().get_with(|name| name.ready().cloned()),
+///     Some("user 1".to_string())
+/// );
+/// user_id().set(0);
+/// assert!(user_name().get_with(Loadable::is_failed));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Loadable<T, E = String> {
+    #[default]
+    Loading,
+    Ready(T),
+    Failed(E),
+}
+
+impl<T, E> Loadable<T, E> {
+    pub fn is_loading(&self) -> bool {
+        matches!(self, Loadable::Loading)
+    }
+
+    pub fn is_ready(&self) -> bool {
+        matches!(self, Loadable::Ready(_))
+    }
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Loadable::Failed(_))
+    }
+
+    pub fn ready(&self) -> Option<&T> {
+        match self {
+            Loadable::Ready(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn error(&self) -> Option<&E> {
+        match self {
+            Loadable::Failed(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl<T, E> From<Result<T, E>> for Loadable<T, E> {
+    fn from(result: Result<T, E>) -> Loadable<T, E> {
+        match result {
+            Ok(value) => Loadable::Ready(value),
+            Err(error) => Loadable::Failed(error),
+        }
+    }
+}
+
+/// Names the `Result` an async `#[reaction]` returning `Reaction<L>` resolves
+/// to. Used by the code `#[reaction]` generates.
+#[doc(hidden)]
+pub trait LoadableResult {
+    type Result;
+}
+
+impl<T, E> LoadableResult for Loadable<T, E> {
+    type Result = Result<T, E>;
+}
+
+/// Drives the futures of async reactions which do not complete when first
+/// polled.
+///
+/// Futures are spawned on the executor set with `set_executor` for the
+/// current store, and always polled from the thread that spawned them. Without
+/// an executor, such reactions fail with `AtomicHooksError::NoExecutor`.
+pub trait Executor {
+    fn spawn_local(&self, future: Pin<Box<dyn Future<Output = ()>>>);
+}
+
+/// Sets the executor async reactions of the current store are spawned on.
+pub fn set_executor<X: Executor + 'static>(executor: X) {
+    with_store(|store_refcell| store_refcell.borrow_mut().set_executor(Rc::new(executor)))
+}
+
+// Wakes nothing, for the first poll of an async reaction's future, which is
+// driven by the executor afterwards.
+struct NoopWake;
+
+impl Wake for NoopWake {
+    fn wake(self: Arc<Self>) {}
+}
+
+struct WakeFlag(AtomicBool);
+
+impl Wake for WakeFlag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+struct Task {
+    woken: Arc<WakeFlag>,
+    future: Pin<Box<dyn Future<Output = ()>>>,
+}
+
+/// An executor which only polls its futures when told to, for tests and for
+/// applications driving their own event loop.
+///
+/// Clones share the same queue of futures, so a clone can be handed to
+/// `set_executor` and the original kept to run them.
+#[derive(Clone, Default)]
+pub struct ManualExecutor {
+    tasks: Rc<RefCell<Vec<Task>>>,
+}
+
+impl ManualExecutor {
+    pub fn new() -> ManualExecutor {
+        ManualExecutor::default()
+    }
+
+    /// Polls woken futures until every remaining future is waiting to be
+    /// woken.
+    pub fn run_until_stalled(&self) {
+        loop {
+            // polling may spawn further futures, so the queue must not be
+            // borrowed while doing so
+            let (woken, waiting) = self
+                .tasks
+                .borrow_mut()
+                .drain(..)
+                .partition::<Vec<_>, _>(|task| task.woken.0.load(Ordering::SeqCst));
+            self.tasks.borrow_mut().extend(waiting);
+            if woken.is_empty() {
+                return;
+            }
+
+            for mut task in woken {
+                task.woken.0.store(false, Ordering::SeqCst);
+                let waker = Waker::from(task.woken.clone());
+                let mut cx = Context::from_waker(&waker);
+                if task.future.as_mut().poll(&mut cx).is_pending() {
+                    self.tasks.borrow_mut().push(task);
+                }
+            }
+        }
+    }
+
+    /// The number of futures that have not completed yet.
+    pub fn pending(&self) -> usize {
+        self.tasks.borrow().len()
+    }
+}
+
+impl Executor for ManualExecutor {
+    fn spawn_local(&self, future: Pin<Box<dyn Future<Output = ()>>>) {
+        self.tasks.borrow_mut().push(Task {
+            woken: Arc::new(WakeFlag(AtomicBool::new(true))),
+            future,
+        });
+    }
+}
+
+// Polls the future of an async reaction for as long as it is the reaction's
+// latest one. The dependencies observed by every poll are collected in
+// `context`, and those no longer observed are unlinked once it completes.
+struct AsyncReaction<T, E> {
+    id: StorageKey,
+    generation: u64,
+    runtime: Option<Runtime>,
+    context: ReactiveContext,
+    future: Pin<Box<dyn Future<Output = Result<T, E>>>>,
+}
+
+impl<T: Storable, E: Storable> AsyncReaction<T, E> {
+    fn poll_in_store(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let (id, generation) = (self.id, self.generation);
+        let is_current = with_store(|store_refcell| {
+            store_refcell
+                .borrow()
+                .is_current_async_task(&id, generation)
+        });
+        if !is_current {
+            // superseded or disposed of, drop the future without polling it
+            return Poll::Ready(());
+        }
+
+        let future = &mut self.future;
+        let (poll, context) = illicit::Layer::new()
+            .offer(RefCell::new(self.context.clone()))
+            .enter(|| {
+                let poll = future.as_mut().poll(cx);
+                if poll.is_ready() {
+                    unlink_dead_links(id);
+                }
+                let context = illicit::expect::<RefCell<ReactiveContext>>();
+                let context = context.borrow().clone();
+                (poll, context)
+            });
+        self.context = context;
+        match poll {
+            Poll::Pending => {
+                let waker = cx.waker().clone();
+                with_store(|store_refcell| store_refcell.borrow_mut().park_async_task(&id, waker));
+                Poll::Pending
+            }
+            Poll::Ready(result) => {
+                with_store(|store_refcell| store_refcell.borrow_mut().cancel_async_task(&id));
+                set_atom_state_with_id(Loadable::from(result), id);
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+impl<T: Storable, E: Storable> Future for AsyncReaction<T, E> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        match this.runtime.clone() {
+            Some(runtime) => runtime.enter(|| this.poll_in_store(cx)),
+            None => this.poll_in_store(cx),
+        }
+    }
+}
+
+/// Runs the future of an async reaction, superseding the future it was
+/// previously running. Called by the code `#[reaction]` generates for an
+/// `async fn`.
+///
+/// The future is polled once straight away, so dependencies observed before
+/// its first `.await` are tracked like those of any other reaction. If it does
+/// not complete, the reaction is `Loading` until the future is driven to
+/// completion on the store's executor. The dependencies of the previous run
+/// are kept until then, and those the new run did not observe are unlinked
+/// once it completes.
+pub fn run_async_reaction<T, E, Fut>(id: StorageKey, future: Fut)
+where
+    T: Storable,
+    E: Storable + From<AtomicHooksError>,
+    Fut: Future<Output = Result<T, E>> + 'static,
+{
+    let (generation, stale) =
+        with_store(|store_refcell| store_refcell.borrow_mut().start_async_task(&id));
+    if let Some(stale) = stale {
+        stale.wake();
+    }
+
+    let mut future: Pin<Box<dyn Future<Output = Result<T, E>>>> = Box::pin(future);
+    let waker = Waker::from(Arc::new(NoopWake));
+    let mut cx = Context::from_waker(&waker);
+    if let Poll::Ready(result) = future.as_mut().poll(&mut cx) {
+        with_store(|store_refcell| store_refcell.borrow_mut().cancel_async_task(&id));
+        set_inert_atom_state_with_id(Loadable::from(result), id);
+        unlink_dead_links(id);
+        return;
+    }
+
+    let executor = match with_store(|store_refcell| store_refcell.borrow().executor()) {
+        Some(executor) => executor,
+        None => {
+            with_store(|store_refcell| store_refcell.borrow_mut().cancel_async_task(&id));
+            let error = E::from(AtomicHooksError::NoExecutor { id });
+            set_inert_atom_state_with_id(Loadable::<T, E>::Failed(error), id);
+            unlink_dead_links(id);
+            return;
+        }
+    };
+    set_inert_atom_state_with_id(Loadable::<T, E>::Loading, id);
+    // the dependencies observed before the first `.await`
+    let context = illicit::get::<RefCell<ReactiveContext>>()
+        .map(|context| context.borrow().clone())
+        .unwrap_or_else(|_| ReactiveContext::new(id));
+    executor.spawn_local(Box::pin(AsyncReaction {
+        id,
+        generation,
+        runtime: illicit::get::<Runtime>()
+            .ok()
+            .map(|runtime| runtime.clone()),
+        context,
+        future,
+    }));
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        reactive_state_access::{atom::Atom, reaction::Reaction, CloneReactiveState},
+        reactive_state_functions::with_store,
+        *,
+    };
+    use std::{
+        cell::Cell,
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    thread_local! {
+        static LOOKUPS_COMPLETED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    // Pending on its first poll, like a request waiting on the network.
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    async fn lookup(id: u32) -> Result<String, String> {
+        YieldOnce(false).await;
+        LOOKUPS_COMPLETED.with(|count| count.set(count.get() + 1));
+        if id == 0 {
+            Err(format!("no user {}", id))
+        } else {
+            Ok(format!("user {}", id))
+        }
+    }
+
+    #[atom]
+    fn user_id() -> Atom<u32> {
+        1
+    }
+
+    #[reaction]
+    async fn user_name() -> Reaction<Loadable<String>> {
+        let id = user_id().observe();
+        let name = lookup(id).await?;
+        Ok(name)
+    }
+
+    #[reaction]
+    fn greeting() -> Reaction<String> {
+        match user_name().observe() {
+            Loadable::Loading => "loading".to_string(),
+            Loadable::Ready(name) => format!("hello {}", name),
+            Loadable::Failed(error) => error,
+        }
+    }
+
+    #[atom]
+    fn use_nickname() -> Atom<bool> {
+        false
+    }
+
+    #[atom]
+    fn nickname() -> Atom<String> {
+        "nick".to_string()
+    }
+
+    #[reaction]
+    async fn display_name() -> Reaction<Loadable<String>> {
+        let name = lookup(user_id().observe()).await?;
+        if use_nickname().observe() {
+            Ok(nickname().observe())
+        } else {
+            Ok(name)
+        }
+    }
+
+    fn observes_nickname() -> bool {
+        let (source, dependent) = (nickname().id, display_name().id);
+        with_store(|store_refcell| {
+            store_refcell
+                .borrow()
+                .dependent_ids(&source)
+                .contains(&dependent)
+        })
+    }
+
+    fn manual_executor() -> ManualExecutor {
+        let executor = ManualExecutor::new();
+        set_executor(executor.clone());
+        executor
+    }
+
+    #[test]
+    fn test_async_reaction_loads() {
+        let executor = manual_executor();
+
+        let name = user_name();
+        assert_eq!(name.get(), Loadable::Loading);
+        assert_eq!(executor.pending(), 1);
+
+        executor.run_until_stalled();
+        assert_eq!(name.get(), Loadable::Ready("user 1".to_string()));
+        assert_eq!(executor.pending(), 0);
+    }
+
+    #[test]
+    fn test_async_reaction_fails() {
+        let executor = manual_executor();
+        user_id().set(0);
+
+        let name = user_name();
+        executor.run_until_stalled();
+        assert_eq!(name.get(), Loadable::Failed("no user 0".to_string()));
+    }
+
+    #[test]
+    fn test_stale_futures_are_cancelled() {
+        let executor = manual_executor();
+        let name = user_name();
+
+        user_id().set(2);
+        user_id().set(3);
+        assert_eq!(name.get(), Loadable::Loading);
+        assert_eq!(executor.pending(), 3);
+
+        executor.run_until_stalled();
+        assert_eq!(name.get(), Loadable::Ready("user 3".to_string()));
+        assert_eq!(LOOKUPS_COMPLETED.with(Cell::get), 1);
+        assert_eq!(executor.pending(), 0);
+    }
+
+    #[test]
+    fn test_dependents_see_loaded_values() {
+        let executor = manual_executor();
+        let greeting = greeting();
+        assert_eq!(greeting.get(), "loading");
+
+        executor.run_until_stalled();
+        assert_eq!(greeting.get(), "hello user 1");
+
+        user_id().set(4);
+        assert_eq!(greeting.get(), "loading");
+        executor.run_until_stalled();
+        assert_eq!(greeting.get(), "hello user 4");
+    }
+
+    #[test]
+    fn test_async_reaction_in_runtime() {
+        let executor = ManualExecutor::new();
+        let runtime = Runtime::new();
+        let name = runtime.enter(|| {
+            set_executor(executor.clone());
+            user_id().set(5);
+            user_name()
+        });
+
+        executor.run_until_stalled();
+        assert_eq!(
+            runtime.enter(|| name.get()),
+            Loadable::Ready("user 5".to_string())
+        );
+        assert_eq!(user_id().get(), 1, "the default store is untouched");
+    }
+
+    #[test]
+    fn test_pending_reaction_without_executor_fails() {
+        let name = user_name();
+        assert_eq!(
+            name.get(),
+            Loadable::Failed(AtomicHooksError::NoExecutor { id: name.id }.to_string())
+        );
+    }
+
+    #[test]
+    fn test_dependencies_after_await_are_unlinked() {
+        let executor = manual_executor();
+        use_nickname().set(true);
+        let name = display_name();
+        executor.run_until_stalled();
+        assert_eq!(name.get(), Loadable::Ready("nick".to_string()));
+        assert!(observes_nickname());
+
+        use_nickname().set(false);
+        executor.run_until_stalled();
+        assert_eq!(name.get(), Loadable::Ready("user 1".to_string()));
+        assert!(!observes_nickname());
+    }
+
+    #[test]
+    fn test_writes_to_dependencies_after_await_rerun() {
+        let executor = manual_executor();
+        use_nickname().set(true);
+        let name = display_name();
+        executor.run_until_stalled();
+
+        nickname().set("second".to_string());
+        executor.run_until_stalled();
+        assert_eq!(name.get(), Loadable::Ready("second".to_string()));
+
+        user_id().set(2);
+        assert!(name.get().is_loading());
+        assert!(observes_nickname(), "kept while the new run is pending");
+        nickname().set("third".to_string());
+        executor.run_until_stalled();
+        assert_eq!(name.get(), Loadable::Ready("third".to_string()));
+        assert!(observes_nickname());
+    }
+}
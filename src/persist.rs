@@ -0,0 +1,440 @@
+use crate::{
+    event_log::StoreEvent,
+    reactive_state_functions::with_store,
+    store::{Storable, StorageKey},
+};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+/// Where atoms marked `#[atom(persist = "key")]` load their initial value
+/// from and save every write to. Values are JSON documents.
+///
+/// ```
+/// use atomic_hooks::{atom::Atom, *};
+///
+/// #[atom(persist = "settings.theme")]
+/// fn theme() -> Atom<String> {
+///     "light".to_string()
+/// }
+///
+/// let backend = MemoryBackend::new();
+/// backend.save("settings.theme", "\"dark\"").unwrap();
+/// set_persist_backend(backend.clone());
+///
+/// assert_eq!(theme().get_with(|theme| theme.clone()), "dark");
+/// theme().set("solarized".to_string());
+/// assert_eq!(backend.load("settings.theme"), Some("\"solarized\"".to_string()));
+/// ```
+pub trait PersistBackend: Storable {
+    fn load(&self, key: &str) -> Option<String>;
+    fn save(&self, key: &str, value: &str) -> std::io::Result<()>;
+}
+
+/// Sets the backend persisted atoms of the current store use. Atoms created
+/// before the backend is set start from their default value.
+pub fn set_persist_backend<B: PersistBackend>(backend: B) {
+    with_store(|store_refcell| {
+        store_refcell
+            .borrow_mut()
+            .set_persist_backend(Arc::new(backend))
+    })
+}
+
+/// A write of a persisted atom which failed to serialize, with an error of
+/// kind `InvalidData`, or which the backend failed to save. The value stays in
+/// the store.
+#[derive(Debug)]
+pub struct PersistError {
+    pub id: StorageKey,
+    pub key: &'static str,
+    pub error: std::io::Error,
+}
+
+impl std::fmt::Display for PersistError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to persist {}: {}", self.key, self.error)
+    }
+}
+
+impl std::error::Error for PersistError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Bound for the hook passed to `set_persist_error_hook`.
+pub trait PersistErrorFn: Fn(&PersistError) + Storable {}
+impl<F: Fn(&PersistError) + Storable> PersistErrorFn for F {}
+
+/// Calls the hook whenever the backend of the current store fails to save a
+/// write. Failures are also recorded in the event log, if it is enabled.
+///
+/// Like the backend itself, the hook runs once the write has been stored and
+/// is free to access atoms, e.g. to show the failure or queue a retry.
+pub fn set_persist_error_hook<F: PersistErrorFn>(hook: F) {
+    with_store(|store_refcell| {
+        store_refcell
+            .borrow_mut()
+            .set_persist_error_hook(Arc::new(hook))
+    })
+}
+
+/// Stores each key as a JSON file named after the key in a directory, e.g.
+/// `settings.theme` as `<dir>/settings.theme.json`. `%`, `/` and `\` are
+/// percent-encoded in file names, so `a/b` is stored as `a%2Fb.json`.
+#[derive(Clone, Debug)]
+pub struct FileBackend {
+    dir: PathBuf,
+}
+
+impl FileBackend {
+    /// The directory is created on the first save.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> FileBackend {
+        FileBackend { dir: dir.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let mut file_name = String::with_capacity(key.len());
+        for c in key.chars() {
+            match c {
+                '%' => file_name.push_str("%25"),
+                '/' => file_name.push_str("%2F"),
+                '\\' => file_name.push_str("%5C"),
+                c => file_name.push(c),
+            }
+        }
+        self.dir.join(format!("{}.json", file_name))
+    }
+}
+
+impl PersistBackend for FileBackend {
+    fn load(&self, key: &str) -> Option<String> {
+        std::fs::read_to_string(self.path(key)).ok()
+    }
+
+    fn save(&self, key: &str, value: &str) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.path(key), value)
+    }
+}
+
+/// Keeps values in memory, e.g. for tests. Clones share the same values.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryBackend {
+    values: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        MemoryBackend::default()
+    }
+}
+
+impl PersistBackend for MemoryBackend {
+    fn load(&self, key: &str) -> Option<String> {
+        self.values.lock().unwrap().get(key).cloned()
+    }
+
+    fn save(&self, key: &str, value: &str) -> std::io::Result<()> {
+        self.values
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+}
+
+/// The key a persisted atom is saved under.
+pub(crate) struct PersistedAs<T> {
+    pub(crate) key: &'static str,
+    pub(crate) encode: fn(&T) -> Result<String, serde_json::Error>,
+}
+
+impl<T> Clone for PersistedAs<T> {
+    fn clone(&self) -> PersistedAs<T> {
+        *self
+    }
+}
+
+impl<T> Copy for PersistedAs<T> {}
+
+/// A write of a persisted atom, queued while the store is borrowed.
+pub(crate) struct PendingSave {
+    pub(crate) id: StorageKey,
+    pub(crate) key: &'static str,
+    pub(crate) value: Result<String, serde_json::Error>,
+}
+
+/// Saves the writes of persisted atoms queued since the last call. A write
+/// which failed to serialize or save leaves the value in the store as is, and is recorded in the event log
+/// and passed to the persist error hook.
+pub(crate) fn save_pending_writes() {
+    let (saves, backend, hook) = with_store(|store_refcell| {
+        let mut store = store_refcell.borrow_mut();
+        (
+            store.take_pending_saves(),
+            store.persist_backend(),
+            store.persist_error_hook(),
+        )
+    });
+    let backend = match backend {
+        Some(backend) => backend,
+        None => return,
+    };
+    for PendingSave { id, key, value } in saves {
+        let saved = value
+            .map_err(std::io::Error::from)
+            .and_then(|value| backend.save(key, &value));
+        if let Err(error) = saved {
+            with_store(|store_refcell| {
+                store_refcell.borrow_mut().log(StoreEvent::PersistFailed {
+                    id,
+                    key: key.to_string(),
+                    error: error.to_string(),
+                })
+            });
+            if let Some(hook) = &hook {
+                hook(&PersistError { id, key, error });
+            }
+        }
+    }
+}
+
+/// Loads the persisted value of the atom, `None` if there is no backend, no
+/// value for the key or the value does not decode to T.
+pub(crate) fn load_persisted<T: serde::de::DeserializeOwned>(key: &str) -> Option<T> {
+    let backend = with_store(|store_refcell| store_refcell.borrow().persist_backend())?;
+    serde_json::from_str(&backend.load(key)?).ok()
+}
+
+/// Saves every later write of the atom to the backend.
+pub(crate) fn persist_writes<T: Storable + serde::Serialize>(id: StorageKey, key: &'static str) {
+    with_store(|store_refcell| {
+        store_refcell.borrow_mut().set_state_with_id(
+            PersistedAs::<T> {
+                key,
+                encode: |value| serde_json::to_string(value),
+            },
+            &id,
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        reactive_state_access::{atom::Atom, CloneReactiveState},
+        *,
+    };
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Window {
+        width: u32,
+        height: u32,
+    }
+
+    #[atom(persist = "settings.theme")]
+    fn theme() -> Atom<String> {
+        "light".to_string()
+    }
+
+    #[atom(persist = "settings.window")]
+    fn window() -> Atom<Window> {
+        Window {
+            width: 800,
+            height: 600,
+        }
+    }
+
+    fn memory_backend() -> MemoryBackend {
+        let backend = MemoryBackend::new();
+        set_persist_backend(backend.clone());
+        backend
+    }
+
+    #[test]
+    fn test_persisted_value_replaces_default() {
+        let backend = memory_backend();
+        backend
+            .save("settings.window", r#"{"width":1024,"height":768}"#)
+            .unwrap();
+
+        assert_eq!(
+            window().get(),
+            Window {
+                width: 1024,
+                height: 768
+            }
+        );
+        assert_eq!(theme().get(), "light");
+        assert_eq!(
+            backend.load("settings.theme"),
+            None,
+            "defaults are not saved"
+        );
+    }
+
+    #[test]
+    fn test_writes_are_saved() {
+        let backend = memory_backend();
+
+        theme().set("dark".to_string());
+        assert_eq!(backend.load("settings.theme"), Some("\"dark\"".to_string()));
+
+        window().update(|window| window.width = 1280);
+        assert_eq!(
+            backend.load("settings.window"),
+            Some(r#"{"width":1280,"height":600}"#.to_string())
+        );
+    }
+
+    #[derive(Clone)]
+    struct ReadOnlyBackend;
+
+    impl PersistBackend for ReadOnlyBackend {
+        fn load(&self, _key: &str) -> Option<String> {
+            None
+        }
+
+        fn save(&self, _key: &str, _value: &str) -> std::io::Result<()> {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "read only",
+            ))
+        }
+    }
+
+    #[test]
+    fn test_failed_saves_are_reported() {
+        let failures = Arc::new(Mutex::new(vec![]));
+        let reported = failures.clone();
+        set_persist_backend(ReadOnlyBackend);
+        set_persist_error_hook(move |error: &PersistError| {
+            reported.lock().unwrap().push(error.to_string())
+        });
+        enable_event_log(16);
+
+        theme().set("dark".to_string());
+        assert_eq!(theme().get(), "dark", "the value is kept");
+        assert_eq!(
+            *failures.lock().unwrap(),
+            vec!["failed to persist settings.theme: read only".to_string()]
+        );
+        assert!(event_log().iter().any(|logged| logged.event
+            == StoreEvent::PersistFailed {
+                id: theme().id,
+                key: "settings.theme".to_string(),
+                error: "read only".to_string(),
+            }));
+    }
+
+    #[atom]
+    fn save_status() -> Atom<Option<String>> {
+        None
+    }
+
+    #[test]
+    fn test_error_hook_can_set_atoms() {
+        set_persist_backend(ReadOnlyBackend);
+        set_persist_error_hook(|error: &PersistError| save_status().set(Some(error.to_string())));
+
+        theme().set("dark".to_string());
+        window().update(|window| window.width = 1280);
+
+        assert_eq!(
+            save_status().get(),
+            Some("failed to persist settings.window: read only".to_string())
+        );
+    }
+
+    // JSON objects only have string keys, so this fails to serialize
+    #[atom(persist = "settings.layout")]
+    fn layout() -> Atom<HashMap<(u32, u32), String>> {
+        HashMap::new()
+    }
+
+    #[test]
+    fn test_serialize_failures_are_reported() {
+        let backend = memory_backend();
+        let failures = Arc::new(Mutex::new(vec![]));
+        let reported = failures.clone();
+        set_persist_error_hook(move |error: &PersistError| {
+            reported
+                .lock()
+                .unwrap()
+                .push((error.key, error.error.kind()))
+        });
+        enable_event_log(16);
+
+        layout().update(|layout| {
+            layout.insert((0, 0), "editor".to_string());
+        });
+
+        assert_eq!(backend.load("settings.layout"), None);
+        assert_eq!(
+            *failures.lock().unwrap(),
+            vec![("settings.layout", std::io::ErrorKind::InvalidData)]
+        );
+        assert!(event_log().iter().any(|logged| matches!(
+            &logged.event,
+            StoreEvent::PersistFailed { key, .. } if key == "settings.layout"
+        )));
+    }
+
+    #[test]
+    fn test_decode_failure_falls_back_to_default() {
+        let backend = memory_backend();
+        backend.save("settings.window", "\"not a window\"").unwrap();
+
+        assert_eq!(window().get().width, 800);
+    }
+
+    #[test]
+    fn test_without_backend_atoms_use_defaults() {
+        theme().set("dark".to_string());
+        assert_eq!(theme().get(), "dark");
+    }
+
+    #[test]
+    fn test_file_backend() {
+        let dir = std::env::temp_dir().join(format!("atomic_hooks_persist_{}", std::process::id()));
+        let backend = FileBackend::new(&dir);
+        backend.save("settings.theme", "\"dark\"").unwrap();
+
+        let runtime = Runtime::new();
+        runtime.enter(|| {
+            set_persist_backend(backend.clone());
+            assert_eq!(theme().get(), "dark");
+            theme().set("sepia".to_string());
+        });
+        assert_eq!(
+            std::fs::read_to_string(dir.join("settings.theme.json")).unwrap(),
+            "\"sepia\""
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_backend_keys_do_not_collide() {
+        let dir =
+            std::env::temp_dir().join(format!("atomic_hooks_persist_keys_{}", std::process::id()));
+        let backend = FileBackend::new(&dir);
+        let keys = ["a/b", "a\\b", "a_b", "a%2Fb"];
+        for key in keys.iter() {
+            backend.save(key, &format!("{:?}", key)).unwrap();
+        }
+
+        for key in keys.iter() {
+            assert_eq!(backend.load(key), Some(format!("{:?}", key)));
+        }
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), keys.len());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -31,15 +31,32 @@
 /// retrieved
 pub use crate::marker::*;
 pub use crate::{
-    reactive_state_access::{atom, reaction, reversible_atom},
+    error::{AtomicHooksError, CycleNode, DependencyCycle},
+    event_log::{
+        clear_event_log, disable_event_log, enable_event_log, event_log, event_log_for,
+        event_log_since, log_values_with_debug, LogDebug, LogWithoutDebug, LoggedEvent, StoreEvent,
+    },
+    graph::{dependency_graph, DependencyGraph, GraphNode, NodeKind},
+    probe::Probe,
+    reactive_state_access::{
+        atom, atom_family, atom_family::AtomFamily, reaction, reversible_atom,
+        subscription::Subscription,
+    },
     reactive_state_functions::{
-        atom, atom_reverse, clone_reactive_state_with_id, reaction, reaction_start_suspended,
+        atom, atom_family, atom_reverse, batch, checked_read_reactive_state_with_id,
+        clone_reactive_state_with_id, reaction, reaction_lazy, reaction_start_suspended,
         reactive_state_exists_for_id, read_reactive_state_with_id, remove_reactive_state_with_id,
         return_key_for_type_and_insert_if_required, set_inert_atom_reversible_state_with_id,
-        set_inert_atom_state_with_id, try_read_reactive_state_with_id, unlink_dead_links,
-        update_atom_state_with_id, UndoVec,
+        set_inert_atom_state_with_id, skip_unchanged_writes, subscribe_with_id,
+        try_clone_reactive_state_with_id, try_read_reactive_state_with_id, unlink_dead_links,
+        update_atom_state_with_id, SkipUnchanged, SkipWithoutEq, UndoVec,
     },
-    reverse::{global_reverse_queue, GlobalUndo},
+    reverse::{
+        global_reverse_queue, hint_undo_size, set_undo_scope, undo_group, undo_scope,
+        with_undo_scope, Coalescing, GlobalUndo, HintSize, HintWithoutSize, HistoryEntry, SizeHint,
+        UndoBranch, UndoLimits,
+    },
+    runtime::Runtime,
     store::{ReactiveContext, RxFunc, TopoKey},
 };
 pub use atomic_hooks_macros::{atom, reaction};
@@ -52,10 +69,29 @@ pub use crate::{
     helpers::{do_once, CallSite, Local},
     hooks_state_functions::{
         clone_state_with_topo_id, execute_and_remove_unmounts, new_state, on_unmount,
-        reset_unseen_id_list, set_state_with_topo_id, state_exists_for_topo_id, unseen_ids,
-        update_state_with_topo_id, use_state, use_state_current,
+        reset_unseen_id_list, set_state_with_topo_id, state_exists_for_topo_id,
+        try_clone_state_with_topo_id, unseen_ids, update_state_with_topo_id, use_state,
+        use_state_current,
     },
     unmount::{StateAccessUnmount, Unmount},
 };
 
 pub use crate::reactive_state_access::observable::Observable;
+
+#[cfg(feature = "serde")]
+pub use crate::persist::{
+    set_persist_backend, set_persist_error_hook, FileBackend, MemoryBackend, PersistBackend,
+    PersistError,
+};
+#[cfg(feature = "serde")]
+pub use crate::reactive_state_functions::atom_persisted;
+#[cfg(feature = "serde")]
+pub use crate::snapshot::{
+    restore, restore_history, save_history, serializable_atom, serializable_undo_values, snapshot,
+    snapshot_key, OmittedCommand, SavedCommand, SavedHistory, StoreSnapshot,
+};
+
+#[cfg(not(feature = "sync"))]
+pub use crate::loadable::{
+    run_async_reaction, set_executor, Executor, Loadable, LoadableResult, ManualExecutor,
+};
@@ -0,0 +1,49 @@
+use std::marker::PhantomData;
+
+// `#[atom]` and `#[reaction]` treat the value type of a node differently
+// depending on the traits it implements, which they cannot see. They call
+// `(&Probe::<T>::new()).method(id)`, which resolves to the impl of a trait
+// for `Probe<T>` if T meets its bounds and to the no-op impl of a second
+// trait for `&Probe<T>` otherwise.
+#[doc(hidden)]
+pub struct Probe<T>(PhantomData<T>);
+
+impl<T> Probe<T> {
+    pub fn new() -> Probe<T> {
+        Probe(PhantomData)
+    }
+}
+
+impl<T> Default for Probe<T> {
+    fn default() -> Probe<T> {
+        Probe::new()
+    }
+}
+
+// Declares the pair of traits giving `Probe` a method, which calls
+// `func::<T>(id)` if T meets the bounds and does nothing otherwise.
+macro_rules! probe {
+    ($with:ident, $without:ident, $method:ident, [$($bound:tt)+], $func:ident) => {
+        #[doc(hidden)]
+        pub trait $with {
+            fn $method(&self, id: $crate::store::StorageKey);
+        }
+
+        impl<T: $($bound)+> $with for $crate::probe::Probe<T> {
+            fn $method(&self, id: $crate::store::StorageKey) {
+                $func::<T>(id)
+            }
+        }
+
+        #[doc(hidden)]
+        pub trait $without {
+            fn $method(&self, id: $crate::store::StorageKey);
+        }
+
+        impl<T> $without for &$crate::probe::Probe<T> {
+            fn $method(&self, _id: $crate::store::StorageKey) {}
+        }
+    };
+}
+
+pub(crate) use probe;
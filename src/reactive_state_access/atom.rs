@@ -25,7 +25,7 @@ impl<T> Copy for Atom<T> {}
 
 impl<T> Atom<T>
 where
-    T: 'static,
+    T: Storable,
 {
     /// Instantiate a new atom.
     pub fn new(id: StorageKey) -> Atom<T> {
@@ -68,7 +68,7 @@ where
     /// - Add a description that explains relevant use case for this method
     pub fn inert_set(self, value: T)
     where
-        T: 'static,
+        T: Storable,
     {
         set_inert_atom_state_with_id(value, self.id);
     }
@@ -91,11 +91,17 @@ where
     /// - When to use it
     pub fn set(self, value: T)
     where
-        T: 'static,
+        T: Storable,
     {
         set_atom_state_with_id(value, self.id);
     }
 
+    /// Like `set`, but fails instead of storing the value if the atom holds
+    /// state of another type or is currently being read or updated.
+    pub fn try_set(self, value: T) -> Result<(), AtomicHooksError> {
+        try_set_atom_state_with_id(value, self.id)
+    }
+
     /// Pass a function that update the atom state related
     /// This update will trigger reactions and observers will get the update
     /// ```
@@ -114,11 +120,26 @@ where
     /// atom, then all of them will get the update.
     pub fn update<F: FnOnce(&mut T) -> ()>(&self, func: F)
     where
-        T: 'static,
+        T: Storable,
     {
         update_atom_state_with_id(self.id, func);
     }
 
+    /// Like `update`, but returns an error instead of panicking when the
+    /// state is missing, of another type or already being read or updated.
+    /// ```
+    /// use atomic_hooks::{atom::Atom, *};
+    /// #[atom]
+    /// fn a() -> Atom<i32> {
+    ///     0
+    /// }
+    /// let result = a().get_with(|_| a().try_update(|state| *state = 1));
+    /// assert!(matches!(result, Err(AtomicHooksError::ReentrantBorrow { .. })));
+    /// ```
+    pub fn try_update<F: FnOnce(&mut T)>(&self, func: F) -> Result<(), AtomicHooksError> {
+        try_update_atom_state_with_id(self.id, func)
+    }
+
     /// Use to remove an atom from the global state
     /// ```
     /// use atomic_hooks::atom::Atom;
@@ -153,6 +174,25 @@ where
     pub fn delete(self) {
         self.remove();
     }
+
+    /// Removes the atom from the store altogether: unlike `remove`, which
+    /// only drops the value, this also drops its initialiser and its
+    /// dependency edges. Calling the atom function again starts afresh.
+    /// ```
+    /// use atomic_hooks::{atom::Atom, *};
+    /// #[atom]
+    /// fn a() -> Atom<i32> {
+    ///     0
+    /// }
+    ///
+    /// a().set(10);
+    /// a().dispose();
+    ///
+    /// a().get_with(|v| assert_eq!(v, &0, "The a state is created anew"));
+    /// ```
+    pub fn dispose(self) {
+        dispose_node_with_id(self.id);
+    }
     /// Reset to the initial value
     /// ```
     /// use atomic_hooks::atom::Atom;
@@ -207,6 +247,20 @@ where
         read_reactive_state_with_id(self.id, func)
     }
 
+    /// Like `get_with`, but returns an error instead of panicking when the
+    /// state cannot be read.
+    pub fn try_get_with<F: FnOnce(&T) -> R, R>(&self, func: F) -> Result<R, AtomicHooksError> {
+        checked_read_reactive_state_with_id(self.id, func)
+    }
+
+    /// returns a clone of the stored state, or the reason it cannot be read.
+    pub fn try_get(&self) -> Result<T, AtomicHooksError>
+    where
+        T: Clone,
+    {
+        try_clone_reactive_state_with_id::<T>(self.id)
+    }
+
     /// Triggers the passed function when the atom is updated
     /// This method needs to be used inside a function body that has the
     /// attributes **[reaction]**.
@@ -253,7 +307,7 @@ where
 
 impl<T> Observable<T> for Atom<T>
 where
-    T: 'static,
+    T: Storable,
 {
     fn id(&self) -> StorageKey {
         self.id
@@ -262,7 +316,7 @@ where
 // The below is broke as need None if no prior state
 impl<T> ObserveChangeReactiveState<T> for Atom<T>
 where
-    T: Clone + 'static + PartialEq,
+    T: Clone + Storable + PartialEq,
 {
     /// Let you get the last changes on an Atom state
     ///
@@ -390,7 +444,7 @@ where
 }
 impl<T> CloneReactiveState<T> for Atom<T>
 where
-    T: Clone + 'static,
+    T: Clone + Storable,
 {
     /// returns a clone of the stored state panics if not stored.
     fn get(&self) -> T {
@@ -404,7 +458,7 @@ where
 // If the underlying type provides display then so does the ReactiveStateAccess
 impl<T> std::fmt::Display for Atom<T>
 where
-    T: std::fmt::Display + 'static,
+    T: std::fmt::Display + Storable,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.get_with(|t| write!(f, "{}", t))
@@ -413,10 +467,16 @@ where
 
 use crate::reactive_state_access::{CloneReactiveState, ObserveChangeReactiveState};
 use crate::{
-    clone_reactive_state_with_id, reactive_state_exists_for_id,
-    reactive_state_functions::{execute_reaction_nodes, set_atom_state_with_id},
+    checked_read_reactive_state_with_id, clone_reactive_state_with_id,
+    error::AtomicHooksError,
+    reactive_state_exists_for_id,
+    reactive_state_functions::{
+        dispose_node_with_id, execute_reaction_nodes, set_atom_state_with_id,
+        try_clone_reactive_state_with_id, try_set_atom_state_with_id,
+        try_update_atom_state_with_id,
+    },
     read_reactive_state_with_id, remove_reactive_state_with_id, set_inert_atom_state_with_id,
-    store::StorageKey,
+    store::{Storable, StorageKey},
     update_atom_state_with_id, Observable, RxFunc,
 };
 use std::{
@@ -426,7 +486,7 @@ use std::{
 
 impl<T> Add for Atom<T>
 where
-    T: Copy + Add<Output = T> + 'static,
+    T: Copy + Add<Output = T> + Storable,
 {
     type Output = T;
 
@@ -437,7 +497,7 @@ where
 
 impl<T> Mul for Atom<T>
 where
-    T: Copy + Mul<Output = T> + 'static,
+    T: Copy + Mul<Output = T> + Storable,
 {
     type Output = T;
 
@@ -448,7 +508,7 @@ where
 
 impl<T> Div for Atom<T>
 where
-    T: Copy + Div<Output = T> + 'static,
+    T: Copy + Div<Output = T> + Storable,
 {
     type Output = T;
 
@@ -459,7 +519,7 @@ where
 
 impl<T> Sub for Atom<T>
 where
-    T: Copy + Sub<Output = T> + 'static,
+    T: Copy + Sub<Output = T> + Storable,
 {
     type Output = T;
 
@@ -518,6 +578,24 @@ mod test {
         c.get()
     }
 
+    // Hashes every value to the same location, forcing keys to collide.
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    struct Colliding(u32);
+
+    impl std::hash::Hash for Colliding {
+        fn hash<H: std::hash::Hasher>(&self, _state: &mut H) {}
+    }
+
+    #[atom]
+    fn colliding_atom(n: Colliding) -> Atom<u32> {
+        n.0
+    }
+
+    #[reaction]
+    fn colliding_sum(first: Colliding, second: Colliding) -> Reaction<u32> {
+        colliding_atom(first).observe() + colliding_atom(second).observe()
+    }
+
     #[test]
     fn test_set_atom() {
         let a = a();
@@ -661,4 +739,122 @@ mod test {
         let a_1 = a.clone();
         assert_eq!(a_1.get(), 8, "We should get 8 as value on the copy as well")
     }
+
+    #[test]
+    fn test_try_accessors() {
+        let a = a();
+        assert_eq!(a.try_set(3), Ok(()));
+        assert_eq!(a.try_update(|v| *v += 1), Ok(()));
+        assert_eq!(a.try_get(), Ok(4));
+        assert_eq!(a.try_get_with(|v| v * 2), Ok(8));
+    }
+
+    #[test]
+    fn test_try_get_missing_state() {
+        let a = a();
+        a.remove();
+        assert_eq!(a.try_get(), Err(AtomicHooksError::Missing { id: a.id }));
+        assert_eq!(
+            a.try_update(|v| *v += 1),
+            Err(AtomicHooksError::Missing { id: a.id })
+        );
+        assert_eq!(
+            try_read_reactive_state_with_id::<i32, _, _>(a.id, |v| *v),
+            None
+        );
+    }
+
+    #[test]
+    fn test_try_get_wrong_type() {
+        let a = a();
+        let wrong = Atom::<String>::new(a.id);
+        assert!(matches!(
+            wrong.try_get(),
+            Err(AtomicHooksError::WrongType { expected, found, .. })
+                if expected.contains("String") && found == "i32"
+        ));
+        assert!(matches!(
+            wrong.try_set("hello".to_string()),
+            Err(AtomicHooksError::WrongType { .. })
+        ));
+        assert!(!wrong.state_exists(), "the mistyped value is not stored");
+    }
+
+    #[test]
+    fn test_try_get_reentrant_borrow() {
+        let a = a();
+        let nested = a.get_with(|_| a.try_get());
+        assert_eq!(nested, Err(AtomicHooksError::ReentrantBorrow { id: a.id }));
+        let nested = a.get_with(|_| a.try_set(1));
+        assert_eq!(nested, Err(AtomicHooksError::ReentrantBorrow { id: a.id }));
+        assert_eq!(a.get(), 0);
+    }
+
+    #[test]
+    fn test_panicking_update_checks_state_back_in() {
+        let a = a();
+        let panicked = std::panic::catch_unwind(|| a.update(|_| panic!("failed inside update")));
+        assert!(panicked.is_err());
+        assert_eq!(a.try_get(), Ok(0));
+    }
+
+    #[test]
+    fn test_dispose_while_checked_out() {
+        let a = a();
+        a.set(3);
+        a.update(|v| {
+            a.dispose();
+            *v = 5;
+        });
+
+        assert!(!a.state_exists(), "the update does not bring it back");
+        assert_eq!(self::a().get(), 0);
+    }
+
+    #[test]
+    fn test_try_observe_outside_reaction() {
+        assert_eq!(a().try_observe(), Err(AtomicHooksError::NoReactiveContext));
+        assert_eq!(a().try_observe_with(|v| *v), Ok(0));
+    }
+
+    #[test]
+    fn test_colliding_arguments_get_their_own_atoms() {
+        let atoms = (0..10)
+            .map(|n| colliding_atom(Colliding(n)))
+            .collect::<Vec<_>>();
+        for (n, atom) in atoms.iter().enumerate() {
+            assert_eq!(atom.get(), n as u32);
+        }
+
+        colliding_atom(Colliding(3)).set(30);
+        assert_eq!(atoms[3].get(), 30);
+        assert_eq!(atoms[4].get(), 4, "a colliding neighbour is untouched");
+        assert_eq!(colliding_atom(Colliding(3)).id, atoms[3].id);
+    }
+
+    #[test]
+    fn test_colliding_arguments_in_reactions() {
+        let sum = colliding_sum(Colliding(1), Colliding(2));
+        let swapped = colliding_sum(Colliding(2), Colliding(1));
+        assert_ne!(sum.id, swapped.id);
+        assert_eq!(sum.get(), 3);
+
+        colliding_atom(Colliding(2)).set(20);
+        assert_eq!(sum.get(), 21);
+        assert_eq!(swapped.get(), 21);
+    }
+
+    #[test]
+    fn test_colliding_argument_types() {
+        let (first, second, first_again) = reactive_state_functions::with_store(|store_refcell| {
+            let mut store = store_refcell.borrow_mut();
+            (
+                store.return_key_for_type_and_insert_if_required(7, 1_u32),
+                store.return_key_for_type_and_insert_if_required(7, 1_i64),
+                store.return_key_for_type_and_insert_if_required(7, 1_u32),
+            )
+        });
+        assert_ne!(first, second);
+        assert_eq!(first, first_again);
+    }
 }
@@ -0,0 +1,280 @@
+use crate::{
+    reactive_state_access::atom::Atom,
+    reactive_state_exists_for_id,
+    reactive_state_functions::dispose_node_with_id,
+    read_reactive_state_with_id, remove_reactive_state_with_id,
+    store::{Storable, StorageKey},
+    update_atom_state_with_id, Observable,
+};
+use std::marker::PhantomData;
+
+/// The members of an atom family, in the order they were created.
+#[derive(Clone, PartialEq)]
+pub struct FamilyMembers<P> {
+    members: Vec<(P, StorageKey)>,
+}
+
+impl<P> Default for FamilyMembers<P> {
+    fn default() -> FamilyMembers<P> {
+        FamilyMembers { members: vec![] }
+    }
+}
+
+/// Keeps track of the members of a parameterised atom, i.e. of the atoms
+/// created for each distinct parameter value.
+///
+/// The family is generated by `#[atom(family)]` as `<name>_family()`. For
+/// atoms taking more than one parameter, `P` is the tuple of parameters.
+/// ```
+/// use atomic_hooks::{atom::Atom, *};
+///
+/// #[atom(family)]
+/// fn todo(id: u32) -> Atom<String> {
+///     format!("todo {}", id)
+/// }
+///
+/// todo(1);
+/// todo(2);
+/// assert_eq!(todo_family().keys(), vec![1, 2]);
+///
+/// todo_family().remove(&1);
+/// assert_eq!(todo_family().keys(), vec![2]);
+/// ```
+pub struct AtomFamily<P, T> {
+    pub id: StorageKey,
+    _phantom_data: PhantomData<(P, T)>,
+}
+
+impl<P, T> std::fmt::Debug for AtomFamily<P, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({:#?})", self.id)
+    }
+}
+
+impl<P, T> Clone for AtomFamily<P, T> {
+    fn clone(&self) -> AtomFamily<P, T> {
+        *self
+    }
+}
+
+impl<P, T> Copy for AtomFamily<P, T> {}
+
+impl<P, T> AtomFamily<P, T>
+where
+    P: Storable + Clone + PartialEq,
+    T: Storable,
+{
+    pub fn new(id: StorageKey) -> AtomFamily<P, T> {
+        AtomFamily {
+            id,
+            _phantom_data: PhantomData,
+        }
+    }
+
+    fn members(&self) -> Atom<FamilyMembers<P>> {
+        Atom::new(self.id)
+    }
+
+    /// Records a newly created member. Called by the code `#[atom(family)]`
+    /// generates.
+    #[doc(hidden)]
+    pub fn add_member(&self, param: P, id: StorageKey) {
+        let known = read_reactive_state_with_id::<FamilyMembers<P>, _, _>(self.id, |family| {
+            family.members.iter().any(|(_, member)| *member == id)
+        });
+        if !known {
+            update_atom_state_with_id::<FamilyMembers<P>, _>(self.id, |family| {
+                family
+                    .members
+                    .retain(|(_, member)| reactive_state_exists_for_id::<T>(*member));
+                family.members.push((param, id))
+            });
+        }
+    }
+
+    /// Parameters of the existing members, in the order they were created.
+    pub fn keys(&self) -> Vec<P> {
+        self.iter().map(|(param, _)| param).collect()
+    }
+
+    /// The existing members together with their parameters.
+    pub fn iter(&self) -> std::vec::IntoIter<(P, Atom<T>)> {
+        read_reactive_state_with_id::<FamilyMembers<P>, _, _>(self.id, |family| {
+            Self::existing(family)
+        })
+        .into_iter()
+    }
+
+    pub fn contains(&self, param: &P) -> bool {
+        self.iter().any(|(member, _)| member == *param)
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Like `keys`, but also makes the current `#[reaction]` re-run whenever
+    /// members are added to or removed from the family.
+    pub fn observe_keys(&self) -> Vec<P> {
+        self.members().observe_with(|family| {
+            Self::existing(family)
+                .into_iter()
+                .map(|(param, _)| param)
+                .collect()
+        })
+    }
+
+    /// Disposes of the member for the parameter, returning its value.
+    pub fn remove(&self, param: &P) -> Option<T> {
+        let id = read_reactive_state_with_id::<FamilyMembers<P>, _, _>(self.id, |family| {
+            Self::existing(family)
+                .into_iter()
+                .find(|(member, _)| member == param)
+                .map(|(_, atom)| atom.id)
+        })?;
+
+        let value = remove_reactive_state_with_id::<T>(id);
+        dispose_node_with_id(id);
+        update_atom_state_with_id::<FamilyMembers<P>, _>(self.id, |family| {
+            family
+                .members
+                .retain(|(_, member)| *member != id && reactive_state_exists_for_id::<T>(*member))
+        });
+        value
+    }
+
+    /// Disposes of every member of the family.
+    pub fn clear(&self) {
+        let members = read_reactive_state_with_id::<FamilyMembers<P>, _, _>(self.id, |family| {
+            family.members.clone()
+        });
+        if members.is_empty() {
+            return;
+        }
+
+        for (_, id) in members {
+            dispose_node_with_id(id);
+        }
+        update_atom_state_with_id::<FamilyMembers<P>, _>(self.id, |family| family.members.clear());
+    }
+
+    // members disposed of other than through the family are skipped, and
+    // pruned the next time a member is added or removed
+    fn existing(family: &FamilyMembers<P>) -> Vec<(P, Atom<T>)> {
+        family
+            .members
+            .iter()
+            .filter(|(_, id)| reactive_state_exists_for_id::<T>(*id))
+            .map(|(param, id)| (param.clone(), Atom::new(*id)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FamilyMembers;
+    use crate::{
+        reactive_state_access::{atom::Atom, reaction::Reaction, CloneReactiveState},
+        *,
+    };
+
+    #[atom(family)]
+    fn todo(id: u32) -> Atom<String> {
+        format!("todo {}", id)
+    }
+
+    #[atom(family)]
+    fn cell(row: u32, column: u32) -> Atom<u32> {
+        row * 10 + column
+    }
+
+    #[reaction]
+    fn todo_count() -> Reaction<usize> {
+        todo_family().observe_keys().len()
+    }
+
+    #[test]
+    fn test_family_tracks_members() {
+        assert!(todo_family().is_empty());
+
+        todo(3);
+        todo(1);
+        todo(3);
+
+        assert_eq!(todo_family().keys(), vec![3, 1]);
+        assert!(todo_family().contains(&1));
+        assert!(!todo_family().contains(&2));
+        let values = todo_family()
+            .iter()
+            .map(|(_, todo)| todo.get())
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec!["todo 3".to_string(), "todo 1".to_string()]);
+    }
+
+    #[test]
+    fn test_family_with_several_parameters() {
+        cell(1, 2);
+        cell(2, 1);
+
+        assert_eq!(cell_family().keys(), vec![(1, 2), (2, 1)]);
+        assert_eq!(cell_family().iter().nth(1).unwrap().1.get(), 21);
+    }
+
+    #[test]
+    fn test_family_remove_and_clear() {
+        todo(1).set("changed".to_string());
+        todo(2);
+
+        assert_eq!(todo_family().remove(&1), Some("changed".to_string()));
+        assert_eq!(todo_family().remove(&1), None);
+        assert_eq!(todo_family().keys(), vec![2]);
+        assert_eq!(todo(1).get(), "todo 1", "a removed member starts afresh");
+
+        todo_family().clear();
+        assert!(todo_family().is_empty());
+        assert_eq!(todo(2).get(), "todo 2");
+        assert_eq!(todo_family().keys(), vec![2]);
+    }
+
+    #[test]
+    fn test_family_membership_is_observable() {
+        let count = todo_count();
+        assert_eq!(count.get(), 0);
+
+        todo(1);
+        todo(2);
+        assert_eq!(count.get(), 2);
+
+        todo_family().remove(&1);
+        assert_eq!(count.get(), 1);
+
+        todo_family().clear();
+        assert_eq!(count.get(), 0);
+    }
+
+    #[test]
+    fn test_family_skips_and_prunes_disposed_members() {
+        todo(1);
+        todo(2);
+        todo(1).dispose();
+        assert_eq!(todo_family().keys(), vec![2]);
+        assert_eq!(todo_family().len(), 1);
+
+        todo(1).set("again".to_string());
+        todo(1).dispose();
+        todo(1).set("third".to_string());
+        assert_eq!(todo_family().keys(), vec![2, 1]);
+        let members =
+            read_reactive_state_with_id::<FamilyMembers<u32>, _, _>(todo_family().id, |family| {
+                family.members.len()
+            });
+        assert_eq!(members, 2, "disposed members are pruned");
+
+        assert_eq!(todo_family().remove(&1), Some("third".to_string()));
+        assert_eq!(todo_family().keys(), vec![2]);
+    }
+}
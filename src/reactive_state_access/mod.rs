@@ -1,13 +1,17 @@
 // If the stored type is clone, then implement clone for ReactiveStateAccess
 pub mod atom;
+pub mod atom_family;
 pub mod observable;
 pub mod reaction;
 pub mod reversible_atom;
 pub mod state_access;
+pub mod subscription;
+
+use crate::store::Storable;
 
 pub trait CloneReactiveState<T>
 where
-    T: Clone + 'static,
+    T: Clone + Storable,
 {
     fn get(&self) -> T;
     fn soft_get(&self) -> Option<T>;
@@ -15,7 +19,7 @@ where
 
 pub trait ObserveChangeReactiveState<T>
 where
-    T: Clone + 'static + PartialEq,
+    T: Clone + Storable + PartialEq,
 {
     fn observe_change(&self) -> (Option<T>, T);
     fn has_changed(&self) -> bool;
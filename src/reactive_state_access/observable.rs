@@ -1,40 +1,42 @@
+use crate::error::AtomicHooksError;
 use crate::reactive_state_access::state_access::CloneState;
+use crate::reactive_state_access::subscription::Subscription;
 use crate::reactive_state_access::CloneReactiveState;
-use crate::reactive_state_functions::STORE;
-use crate::store::StorageKey;
-use crate::{clone_reactive_state_with_id, read_reactive_state_with_id, ReactiveContext};
+use crate::reactive_state_functions::{
+    subscribe_with_id, try_clone_reactive_state_with_id, with_store,
+};
+use crate::store::{Storable, StorageKey, SubscriberFn};
+use crate::{checked_read_reactive_state_with_id, ReactiveContext};
 use std::cell::RefCell;
 
 pub trait Observable<T>
 where
-    T: 'static,
+    T: Storable,
 {
     fn id(&self) -> StorageKey;
     fn observe(&self) -> T
     where
-        T: Clone + 'static,
+        T: Clone + Storable,
     {
-        let context = illicit::get::<RefCell<ReactiveContext>>().expect(
-            "No #[reaction] context found, are you sure you are in one? I.e. does the current \
-             function have a #[reaction] tag?",
-        );
-        context
-            .borrow_mut()
-            .reactive_state_accessors
-            .push(self.id());
-
-        STORE.with(|store_refcell| {
-            store_refcell
-                .borrow_mut()
-                .add_dependency(&self.id(), &context.borrow().key);
-        });
+        self.try_observe().unwrap_or_else(|err| panic!("{}", err))
+    }
+    /// Like `observe`, but returns an error instead of panicking when called
+    /// outside of a `#[reaction]`, when the state is missing or when
+    /// observing it would create a dependency cycle.
+    fn try_observe(&self) -> Result<T, AtomicHooksError>
+    where
+        T: Clone + Storable,
+    {
+        let context = illicit::get::<RefCell<ReactiveContext>>()
+            .map_err(|_| AtomicHooksError::NoReactiveContext)?;
+        add_observer(self.id(), &context)?;
 
-        clone_reactive_state_with_id::<T>(self.id()).unwrap()
+        try_clone_reactive_state_with_id::<T>(self.id())
     }
     #[topo::nested]
     fn observe_update(&self) -> (Option<T>, T)
     where
-        T: 'static + Clone,
+        T: Storable + Clone,
         Self: CloneReactiveState<T>,
     {
         let previous_value_access = crate::hooks_state_functions::use_state(|| None);
@@ -44,18 +46,54 @@ where
         (opt_previous_value, new_value)
     }
     fn observe_with<F: FnOnce(&T) -> R, R>(&self, func: F) -> R {
+        self.try_observe_with(func)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+    /// Like `observe_with`, but returns an error instead of panicking. Outside
+    /// of a `#[reaction]` the state is read without being observed.
+    fn try_observe_with<F: FnOnce(&T) -> R, R>(&self, func: F) -> Result<R, AtomicHooksError> {
         if let Ok(context) = illicit::get::<RefCell<ReactiveContext>>() {
-            context
-                .borrow_mut()
-                .reactive_state_accessors
-                .push(self.id());
-
-            STORE.with(|store_refcell| {
-                store_refcell
-                    .borrow_mut()
-                    .add_dependency(&self.id(), &context.borrow().key);
-            });
+            add_observer(self.id(), &context)?;
         }
-        read_reactive_state_with_id(self.id(), func)
+        checked_read_reactive_state_with_id(self.id(), func)
     }
+    /// Calls `callback` with the previous and the new value every time the
+    /// state changes, until the returned `Subscription` is dropped. Unlike
+    /// `observe` this works outside of any `#[reaction]`.
+    ///
+    /// ```
+    /// use atomic_hooks::{atom::Atom, *};
+    ///
+    /// #[atom]
+    /// fn count() -> Atom<i32> {
+    ///     0
+    /// }
+    ///
+    /// let subscription = count().subscribe(|old, new| println!("{} -> {}", old, new));
+    /// count().set(1);
+    /// drop(subscription);
+    /// ```
+    #[track_caller]
+    fn subscribe<F: SubscriberFn<T>>(&self, callback: F) -> Subscription
+    where
+        T: Clone,
+    {
+        subscribe_with_id(self.id(), callback)
+    }
+}
+
+// Makes the reaction of the context depend on the state. The state is only
+// recorded as observed once the dependency is added, so that a failed attempt,
+// e.g. because of a cycle, leaves nothing behind.
+fn add_observer(
+    id: StorageKey,
+    context: &RefCell<ReactiveContext>,
+) -> Result<(), AtomicHooksError> {
+    with_store(|store_refcell| {
+        store_refcell
+            .borrow_mut()
+            .add_dependency(&id, &context.borrow().key)
+    })?;
+    context.borrow_mut().reactive_state_accessors.push(id);
+    Ok(())
 }
@@ -1,6 +1,11 @@
 use crate::{
-    clone_reactive_state_with_id, reactive_state_exists_for_id, read_reactive_state_with_id,
-    remove_reactive_state_with_id, store::StorageKey, Observable, RxFunc,
+    checked_read_reactive_state_with_id, clone_reactive_state_with_id,
+    error::AtomicHooksError,
+    reactive_state_exists_for_id,
+    reactive_state_functions::{dispose_node_with_id, try_clone_reactive_state_with_id},
+    read_reactive_state_with_id, remove_reactive_state_with_id,
+    store::{Storable, StorageKey},
+    Observable, RxFunc,
 };
 
 use crate::reactive_state_access::{
@@ -95,7 +100,7 @@ impl<T> Copy for Reaction<T> {}
 
 impl<T> Reaction<T>
 where
-    T: 'static,
+    T: Storable,
 {
     /// Create a new reaction
     pub fn new(id: StorageKey) -> Reaction<T> {
@@ -173,6 +178,13 @@ where
         self.remove();
     }
 
+    /// Removes the reaction from the store altogether: its value, its
+    /// function, its dependency edges and the edges pointing at it from the
+    /// state it observes. Upstream changes no longer run it.
+    pub fn dispose(self) {
+        dispose_node_with_id(self.id);
+    }
+
     /// This method force the update of the new combined value
     /// ## Question
     /// - I thought the new value was updated automatically, isn't ?
@@ -251,6 +263,20 @@ where
     pub fn get_with<F: FnOnce(&T) -> R, R>(&self, func: F) -> R {
         read_reactive_state_with_id(self.id, func)
     }
+
+    /// Like `get_with`, but returns an error instead of panicking when the
+    /// state cannot be read.
+    pub fn try_get_with<F: FnOnce(&T) -> R, R>(&self, func: F) -> Result<R, AtomicHooksError> {
+        checked_read_reactive_state_with_id(self.id, func)
+    }
+
+    /// returns a clone of the stored state, or the reason it cannot be read.
+    pub fn try_get(&self) -> Result<T, AtomicHooksError>
+    where
+        T: Clone,
+    {
+        try_clone_reactive_state_with_id::<T>(self.id)
+    }
     /// Triggers the passed function when the atom is updated
     /// This method needs to be use insided a function body that has the
     /// attributes **[reaction]**.
@@ -376,7 +402,7 @@ where
 
 impl<T> Observable<T> for Reaction<T>
 where
-    T: 'static,
+    T: Storable,
 {
     fn id(&self) -> StorageKey {
         self.id
@@ -384,7 +410,7 @@ where
 }
 impl<T> ObserveChangeReactiveState<T> for Reaction<T>
 where
-    T: Clone + 'static + PartialEq,
+    T: Clone + Storable + PartialEq,
 {
     /// Let you get the last changes on a reaction.
     ///
@@ -543,7 +569,7 @@ where
 }
 impl<T> CloneReactiveState<T> for Reaction<T>
 where
-    T: Clone + 'static,
+    T: Clone + Storable,
 {
     /// returns a clone of the stored state panics if not stored.
     fn get(&self) -> T {
@@ -621,6 +647,186 @@ mod test {
     fn b_reversible() -> ReversibleAtom<i32> {
         0
     }
+
+    #[atom]
+    fn diamond_source() -> Atom<i32> {
+        1
+    }
+
+    #[atom]
+    fn diamond_runs() -> Atom<Vec<(i32, i32)>> {
+        vec![]
+    }
+
+    #[reaction]
+    fn diamond_left() -> Reaction<i32> {
+        diamond_source().observe() * 2
+    }
+
+    #[reaction]
+    fn diamond_right() -> Reaction<i32> {
+        diamond_source().observe() * 3
+    }
+
+    #[reaction]
+    fn diamond_bottom() -> Reaction<i32> {
+        let left = diamond_left().observe();
+        let right = diamond_right().observe();
+        diamond_runs().update(|runs| runs.push((left, right)));
+        left + right
+    }
+
+    #[atom]
+    fn cutoff_source() -> Atom<i32> {
+        0
+    }
+
+    #[atom]
+    fn cutoff_runs() -> Atom<u32> {
+        0
+    }
+
+    #[reaction]
+    fn cutoff_sign() -> Reaction<bool> {
+        cutoff_source().observe() >= 0
+    }
+
+    #[reaction]
+    fn cutoff_downstream() -> Reaction<bool> {
+        cutoff_runs().update(|runs| *runs += 1);
+        !cutoff_sign().observe()
+    }
+
+    #[atom(propagate_unchanged)]
+    fn always_source() -> Atom<i32> {
+        0
+    }
+
+    #[reaction]
+    fn always_downstream() -> Reaction<i32> {
+        cutoff_runs().update(|runs| *runs += 1);
+        always_source().observe()
+    }
+
+    // deliberately not PartialEq
+    #[derive(Clone)]
+    struct Unequal(i32);
+
+    #[atom]
+    fn unequal_source() -> Atom<Unequal> {
+        Unequal(0)
+    }
+
+    #[reaction]
+    fn unequal_downstream() -> Reaction<Unequal> {
+        cutoff_runs().update(|runs| *runs += 1);
+        unequal_source().observe()
+    }
+
+    #[atom]
+    fn lazy_source() -> Atom<i32> {
+        1
+    }
+
+    #[atom]
+    fn lazy_runs() -> Atom<u32> {
+        0
+    }
+
+    #[reaction(lazy)]
+    fn lazy_doubled() -> Reaction<i32> {
+        lazy_runs().update(|runs| *runs += 1);
+        lazy_source().observe() * 2
+    }
+
+    #[reaction]
+    fn lazy_consumer() -> Reaction<i32> {
+        lazy_doubled().observe() + 1
+    }
+
+    #[atom]
+    fn dispose_source() -> Atom<i32> {
+        1
+    }
+
+    #[atom]
+    fn dispose_runs() -> Atom<u32> {
+        0
+    }
+
+    #[reaction]
+    fn dispose_doubled() -> Reaction<i32> {
+        dispose_runs().update(|runs| *runs += 1);
+        dispose_source().observe() * 2
+    }
+
+    #[reaction]
+    fn dispose_scaled(factor: i32) -> Reaction<i32> {
+        dispose_source().observe() * factor
+    }
+
+    fn store_size() -> usize {
+        reactive_state_functions::with_store(|store_refcell| store_refcell.borrow().size())
+    }
+
+    #[atom]
+    fn cycle_flag() -> Atom<bool> {
+        false
+    }
+
+    #[reaction]
+    fn cycle_first() -> Reaction<i32> {
+        if cycle_flag().observe() {
+            cycle_second().observe()
+        } else {
+            a().observe()
+        }
+    }
+
+    #[reaction]
+    fn cycle_second() -> Reaction<i32> {
+        cycle_first().observe() + 1
+    }
+
+    #[reaction]
+    fn tolerant_first() -> Reaction<i32> {
+        if cycle_flag().observe() {
+            tolerant_second().try_observe().unwrap_or(-1)
+        } else {
+            a().observe()
+        }
+    }
+
+    #[reaction]
+    fn tolerant_second() -> Reaction<i32> {
+        tolerant_first().observe() + 1
+    }
+
+    thread_local! {
+        static CONSTRUCTION_FAILS: std::cell::Cell<bool> = const { std::cell::Cell::new(true) };
+    }
+
+    #[reaction]
+    fn fails_once() -> Reaction<i32> {
+        if CONSTRUCTION_FAILS.with(|fails| fails.replace(false)) {
+            panic!("failed to construct");
+        }
+        a().observe()
+    }
+
+    #[reaction]
+    fn self_observing() -> Reaction<i32> {
+        self_observing().observe() + 1
+    }
+
+    #[reaction]
+    fn chain(n: u32) -> Reaction<u32> {
+        if n == 0 {
+            diamond_source().observe() as u32
+        } else {
+            chain(n - 1).observe() + 1
+        }
+    }
     #[test]
     fn test_on_changes_on_reaction() {
         let a_b_subtraction = a_b_subtraction();
@@ -800,4 +1006,304 @@ mod test {
             "We should get 20 for subtraction because setting"
         );
     }
+
+    #[test]
+    fn test_diamond_runs_each_reaction_once() {
+        let bottom = diamond_bottom();
+        assert_eq!(bottom.get(), 5);
+        diamond_runs().set(vec![]);
+
+        diamond_source().set(2);
+
+        assert_eq!(bottom.get(), 10);
+        assert_eq!(
+            diamond_runs().get(),
+            vec![(4, 6)],
+            "the bottom reaction should run once and only see updated inputs"
+        );
+    }
+
+    #[test]
+    fn test_deep_chain_propagates() {
+        let depth = 5_000;
+        for n in 0..=depth {
+            chain(n);
+        }
+        assert_eq!(chain(depth).get(), depth + 1);
+
+        diamond_source().set(10);
+
+        assert_eq!(chain(depth).get(), depth + 10);
+    }
+
+    #[test]
+    fn test_unchanged_atom_write_does_not_propagate() {
+        let bottom = diamond_bottom();
+        diamond_runs().set(vec![]);
+
+        diamond_source().set(1);
+        diamond_source().update(|v| *v *= 1);
+
+        assert_eq!(bottom.get(), 5);
+        assert_eq!(diamond_runs().get(), vec![]);
+    }
+
+    #[test]
+    fn test_unchanged_reaction_output_cuts_off_propagation() {
+        let downstream = cutoff_downstream();
+        cutoff_runs().set(0);
+
+        cutoff_source().set(5);
+        assert!(cutoff_sign().get());
+        assert_eq!(cutoff_runs().get(), 0, "the sign did not change");
+
+        cutoff_source().set(-1);
+        assert!(downstream.get());
+        assert_eq!(cutoff_runs().get(), 1);
+    }
+
+    #[test]
+    fn test_propagate_unchanged_opts_out() {
+        let downstream = always_downstream();
+        cutoff_runs().set(0);
+
+        always_source().set(0);
+
+        assert_eq!(downstream.get(), 0);
+        assert_eq!(cutoff_runs().get(), 1);
+    }
+
+    #[test]
+    fn test_types_without_partial_eq_always_propagate() {
+        let downstream = unequal_downstream();
+        cutoff_runs().set(0);
+
+        unequal_source().set(Unequal(0));
+        assert_eq!(cutoff_runs().get(), 1);
+
+        unequal_source().set(Unequal(2));
+        assert_eq!(downstream.get().0, 2);
+        assert_eq!(cutoff_runs().get(), 2);
+    }
+
+    #[test]
+    fn test_lazy_reaction_runs_on_read() {
+        let doubled = lazy_doubled();
+        assert_eq!(
+            lazy_runs().get(),
+            0,
+            "a lazy reaction does not run on creation"
+        );
+        assert!(!doubled.state_exists());
+
+        assert_eq!(doubled.get(), 2);
+        assert_eq!(doubled.get(), 2);
+        assert_eq!(lazy_runs().get(), 1);
+
+        lazy_source().set(2);
+        lazy_source().set(3);
+        assert_eq!(lazy_runs().get(), 1, "upstream writes only mark it dirty");
+
+        assert_eq!(doubled.get_with(|v| *v), 6);
+        assert_eq!(lazy_runs().get(), 2);
+        assert_eq!(lazy_doubled().get(), 6);
+        assert_eq!(lazy_runs().get(), 2);
+    }
+
+    #[test]
+    fn test_lazy_reaction_is_pulled_by_observers() {
+        let consumer = lazy_consumer();
+        assert_eq!(consumer.get(), 3);
+
+        lazy_source().set(5);
+        assert_eq!(consumer.get(), 11);
+        assert_eq!(lazy_runs().get(), 2);
+    }
+
+    #[test]
+    fn test_dispose_returns_store_to_previous_size() {
+        dispose_runs().set(0);
+        let before = store_size();
+
+        let doubled = dispose_doubled();
+        let scaled = (1..5).map(dispose_scaled).collect::<Vec<_>>();
+        assert_eq!(doubled.get(), 2);
+        assert_eq!(scaled[3].get(), 4);
+        assert!(store_size() > before);
+
+        doubled.dispose();
+        for reaction in scaled {
+            reaction.dispose();
+        }
+        dispose_source().dispose();
+        assert_eq!(store_size(), before);
+    }
+
+    #[test]
+    fn test_disposed_reaction_is_unlinked_from_its_sources() {
+        let doubled = dispose_doubled();
+        let source_id = dispose_source().id;
+        doubled.dispose();
+        dispose_runs().set(0);
+
+        dispose_source().set(3);
+
+        assert_eq!(
+            dispose_runs().get(),
+            0,
+            "the disposed reaction does not run"
+        );
+        assert!(!doubled.state_exists());
+        let dependents = reactive_state_functions::with_store(|store_refcell| {
+            store_refcell.borrow().dependent_ids(&source_id)
+        });
+        assert!(dependents.is_empty());
+    }
+
+    #[test]
+    fn test_disposed_atom_starts_afresh() {
+        let doubled = dispose_doubled();
+        dispose_source().set(5);
+        assert_eq!(doubled.get(), 10);
+
+        dispose_source().dispose();
+        assert_eq!(dispose_source().get(), 1);
+        assert_eq!(doubled.get(), 2, "the dependent re-ran on disposal");
+
+        dispose_source().set(4);
+        assert_eq!(doubled.get(), 8, "and observes the new atom");
+    }
+
+    #[test]
+    fn test_dispose_inside_batch_defers_reruns() {
+        let doubled = dispose_doubled();
+        dispose_source().set(5);
+
+        batch(|| {
+            dispose_source().dispose();
+            assert_eq!(doubled.get(), 10, "the dependent re-runs after the batch");
+        });
+        assert_eq!(doubled.get(), 2);
+
+        dispose_source().set(4);
+        assert_eq!(doubled.get(), 8);
+    }
+
+    #[test]
+    fn test_stale_handle_does_not_alias_a_new_node() {
+        let stale = dispose_scaled(2);
+        stale.dispose();
+        let other = dispose_scaled(3);
+
+        assert_ne!(stale.id, other.id);
+        assert_eq!(
+            stale.try_get(),
+            Err(AtomicHooksError::Missing { id: stale.id })
+        );
+        assert_eq!(other.get(), 3);
+    }
+
+    #[test]
+    fn test_batch_runs_reactions_once() {
+        let bottom = diamond_bottom();
+        diamond_runs().set(vec![]);
+
+        batch(|| {
+            diamond_source().set(2);
+            diamond_source().update(|v| *v += 1);
+            assert_eq!(bottom.get(), 5, "propagation is deferred inside a batch");
+        });
+
+        assert_eq!(bottom.get(), 15);
+        assert_eq!(diamond_runs().get(), vec![(6, 9)]);
+    }
+
+    #[test]
+    fn test_nested_batch_defers_until_outermost_exits() {
+        let a_b_subtraction = a_b_subtraction();
+
+        batch(|| {
+            a().set(10);
+            batch(|| b().set(3));
+            assert_eq!(a_b_subtraction.get(), 0);
+        });
+
+        assert_eq!(a_b_subtraction.get(), 7);
+    }
+
+    #[test]
+    fn test_panicking_batch_is_closed() {
+        let a_b_subtraction = a_b_subtraction();
+
+        let panicked = std::panic::catch_unwind(|| {
+            batch(|| {
+                a().set(10);
+                panic!("failed inside the batch");
+            })
+        });
+        assert!(panicked.is_err());
+
+        b().set(3);
+        assert_eq!(a_b_subtraction.get(), 7, "writes propagate again");
+    }
+
+    #[test]
+    fn test_add_dependency_reports_cycle_path() {
+        let second = cycle_second();
+        let first = cycle_first();
+
+        let error = reactive_state_functions::with_store(|store_refcell| {
+            store_refcell
+                .borrow_mut()
+                .add_dependency(&second.id, &first.id)
+        })
+        .unwrap_err();
+        let cycle = match error {
+            AtomicHooksError::Cycle(cycle) => cycle,
+            other => panic!("expected a cycle, got {:?}", other),
+        };
+
+        let path = cycle.path.iter().map(|node| node.id).collect::<Vec<_>>();
+        assert_eq!(path, vec![first.id, second.id, first.id]);
+        assert!(cycle.path.iter().all(|node| node
+            .location
+            .as_ref()
+            .unwrap()
+            .contains("reaction.rs")));
+        assert!(cycle.to_string().starts_with("dependency cycle detected: "));
+    }
+
+    #[test]
+    #[should_panic(expected = "dependency cycle detected")]
+    fn test_dynamic_cycle_panics_with_diagnostic() {
+        cycle_second();
+        cycle_flag().set(true);
+    }
+
+    #[test]
+    #[should_panic(expected = "dependency cycle detected")]
+    fn test_self_observing_reaction_panics_with_diagnostic() {
+        self_observing();
+    }
+
+    #[test]
+    fn test_panicking_construction_is_not_a_cycle() {
+        assert!(std::panic::catch_unwind(fails_once).is_err());
+
+        a().set(4);
+        assert_eq!(fails_once().get(), 4);
+    }
+
+    #[test]
+    fn test_failed_try_observe_is_not_recorded() {
+        let second = tolerant_second();
+        let first = tolerant_first();
+        cycle_flag().set(true);
+
+        assert_eq!(first.get(), -1);
+        assert!(!read_reactive_state_with_id::<ReactiveContext, _, _>(
+            first.id,
+            |context| { context.reactive_state_accessors.contains(&second.id) }
+        ));
+    }
 }
@@ -1,13 +1,16 @@
 use crate::{
-    clone_reactive_state_with_id,
+    checked_read_reactive_state_with_id, clone_reactive_state_with_id,
+    error::AtomicHooksError,
     reactive_state_access::CloneReactiveState,
     reactive_state_exists_for_id,
     reactive_state_functions::{
-        execute_reaction_nodes, remove_reactive_reversible_state_with_id,
-        set_atom_reversible_state_with_id, update_atom_reversible_state_with_id,
+        dispose_node_with_id, execute_reaction_nodes, remove_reactive_reversible_state_with_id,
+        set_atom_reversible_state_with_id, try_clone_reactive_state_with_id,
+        try_set_atom_reversible_state_with_id, try_update_atom_reversible_state_with_id,
+        update_atom_reversible_state_with_id,
     },
     read_reactive_state_with_id, set_inert_atom_reversible_state_with_id,
-    store::StorageKey,
+    store::{Storable, StorageKey},
     Observable, RxFunc,
 };
 
@@ -89,7 +92,7 @@ where
 
 impl<T> Observable<T> for ReversibleAtom<T>
 where
-    T: 'static + Clone,
+    T: Storable + Clone,
 {
     fn id(&self) -> StorageKey {
         self.id
@@ -100,7 +103,7 @@ impl<T> Copy for ReversibleAtom<T> where T: Clone {}
 
 impl<T> ReversibleAtom<T>
 where
-    T: 'static + Clone,
+    T: Storable + Clone,
 {
     pub fn new(id: StorageKey) -> ReversibleAtom<T> {
         ReversibleAtom {
@@ -143,7 +146,7 @@ where
     /// - need to add description when the use of this method is relevant.
     pub fn inert_set(self, value: T)
     where
-        T: 'static,
+        T: Storable,
     {
         set_inert_atom_reversible_state_with_id(value, self.id);
     }
@@ -162,10 +165,16 @@ where
     /// - When to use it
     pub fn set(self, value: T)
     where
-        T: 'static,
+        T: Storable,
     {
         set_atom_reversible_state_with_id(value, self.id);
     }
+
+    /// Like `set`, but fails instead of storing the value if the atom holds
+    /// state of another type or is currently being read or updated.
+    pub fn try_set(self, value: T) -> Result<(), AtomicHooksError> {
+        try_set_atom_reversible_state_with_id(value, self.id)
+    }
     /// This is use for example when we want to update a component rendering
     /// depending of a state. We update the atom so the component will
     /// rerender with the new state. If many components subscribed to the
@@ -181,11 +190,17 @@ where
     /// ```
     pub fn update<F: FnOnce(&mut T) -> ()>(&self, func: F)
     where
-        T: 'static,
+        T: Storable,
     {
         update_atom_reversible_state_with_id(self.id, func);
     }
 
+    /// Like `update`, but returns an error instead of panicking when the
+    /// state is missing, of another type or already being read or updated.
+    pub fn try_update<F: FnOnce(&mut T)>(&self, func: F) -> Result<(), AtomicHooksError> {
+        try_update_atom_reversible_state_with_id(self.id, func)
+    }
+
     /// ```
     /// use atomic_hooks::reversible_atom::ReversibleAtom;
     /// #[atom(reversible)]
@@ -217,6 +232,12 @@ where
     pub fn delete(self) {
         self.remove();
     }
+
+    /// Removes the atom from the store altogether, including its initialiser
+    /// and dependency edges. Disposal is not recorded in the undo history.
+    pub fn dispose(self) {
+        dispose_node_with_id(self.id);
+    }
     /// Reset to the initial value.
     /// ```
     /// use atomic_hooks::reversible_atom::ReversibleAtom;
@@ -269,6 +290,20 @@ where
         read_reactive_state_with_id(self.id, func)
     }
 
+    /// Like `get_with`, but returns an error instead of panicking when the
+    /// state cannot be read.
+    pub fn try_get_with<F: FnOnce(&T) -> R, R>(&self, func: F) -> Result<R, AtomicHooksError> {
+        checked_read_reactive_state_with_id(self.id, func)
+    }
+
+    /// returns a clone of the stored state, or the reason it cannot be read.
+    pub fn try_get(&self) -> Result<T, AtomicHooksError>
+    where
+        T: Clone,
+    {
+        try_clone_reactive_state_with_id::<T>(self.id)
+    }
+
     // #[topo::nested]
     // pub fn on_update<F: FnOnce() -> R,R>(&self, func:F) -> Option<R> {
     //     let first_call = use_state(||true);
@@ -283,7 +318,7 @@ where
 }
 impl<T> CloneReactiveState<T> for ReversibleAtom<T>
 where
-    T: Clone + 'static,
+    T: Clone + Storable,
 {
     /// returns a clone of the stored state panics if not stored.
     fn get(&self) -> T {
@@ -1,4 +1,4 @@
-use crate::{hooks_state_functions::*, store::TopoKey, Observable};
+use crate::{error::AtomicHooksError, hooks_state_functions::*, store::TopoKey, Observable};
 use std::marker::PhantomData;
 
 ///  Accessor struct that provides access to getting and setting the
@@ -27,7 +27,7 @@ impl<T> Clone for StateAccess<T> {
 
 impl<T> StateAccess<T>
 where
-    T: 'static,
+    T: Storable,
 {
     pub fn new(id: TopoKey) -> StateAccess<T> {
         StateAccess {
@@ -41,6 +41,10 @@ where
         set_state_with_topo_id(value, self.id);
     }
 
+    pub fn try_set(self, value: T) -> Result<(), AtomicHooksError> {
+        try_set_state_with_topo_id(value, self.id)
+    }
+
     pub fn remove(self) -> Option<T> {
         remove_state_with_topo_id(self.id)
     }
@@ -60,6 +64,10 @@ where
         update_state_with_topo_id(self.id, func);
     }
 
+    pub fn try_update<F: FnOnce(&mut T)>(self, func: F) -> Result<(), AtomicHooksError> {
+        try_update_state_with_topo_id(self.id, func)
+    }
+
     pub fn state_exists(self) -> bool {
         state_exists_for_topo_id::<T>(self.id)
     }
@@ -67,11 +75,23 @@ where
     pub fn get_with<F: FnOnce(&T) -> R, R>(self, func: F) -> R {
         read_state_with_topo_id(self.id, func)
     }
+
+    pub fn try_get_with<F: FnOnce(&T) -> R, R>(self, func: F) -> Result<R, AtomicHooksError> {
+        try_read_state_with_topo_id(self.id, func)
+    }
+
+    /// returns a clone of the stored state, or the reason it cannot be read.
+    pub fn try_get(self) -> Result<T, AtomicHooksError>
+    where
+        T: Clone,
+    {
+        try_clone_state_with_topo_id::<T>(self.id)
+    }
 }
 
 pub trait CloneState<T>
 where
-    T: Clone + 'static,
+    T: Clone + Storable,
 {
     fn get(&self) -> T;
 
@@ -80,7 +100,7 @@ where
 
 impl<T> CloneState<T> for StateAccess<T>
 where
-    T: Clone + 'static,
+    T: Clone + Storable,
 {
     /// returns a clone of the stored state panics if not stored.
     fn get(&self) -> T {
@@ -101,7 +121,7 @@ pub trait ChangedState {
 
 impl<T> ChangedState for StateAccess<T>
 where
-    T: Clone + 'static + PartialEq,
+    T: Clone + Storable + PartialEq,
 {
     fn changed(&self) -> bool {
         if let Some(old_state) = clone_state_with_topo_id::<ChangedWrapper<T>>(self.id) {
@@ -115,19 +135,19 @@ where
 
 impl<T> std::fmt::Display for StateAccess<T>
 where
-    T: std::fmt::Display + 'static,
+    T: std::fmt::Display + Storable,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.get_with(|t| format!("{}", t)))
     }
 }
 
-use crate::store::StorageKey;
+use crate::store::{Storable, StorageKey};
 use std::ops::{Add, Div, Mul, Sub};
 
 impl<T> Add for StateAccess<T>
 where
-    T: Copy + Add<Output = T> + 'static,
+    T: Copy + Add<Output = T> + Storable,
 {
     type Output = T;
 
@@ -138,7 +158,7 @@ where
 
 impl<T> Mul for StateAccess<T>
 where
-    T: Copy + Mul<Output = T> + 'static,
+    T: Copy + Mul<Output = T> + Storable,
 {
     type Output = T;
 
@@ -149,7 +169,7 @@ where
 
 impl<T> Div for StateAccess<T>
 where
-    T: Copy + Div<Output = T> + 'static,
+    T: Copy + Div<Output = T> + Storable,
 {
     type Output = T;
 
@@ -160,7 +180,7 @@ where
 
 impl<T> Sub for StateAccess<T>
 where
-    T: Copy + Sub<Output = T> + 'static,
+    T: Copy + Sub<Output = T> + Storable,
 {
     type Output = T;
 
@@ -171,7 +191,7 @@ where
 
 impl<T> Observable<T> for StateAccess<T>
 where
-    T: 'static,
+    T: Storable,
 {
     fn id(&self) -> StorageKey {
         StorageKey::TopoKey(self.id)
@@ -0,0 +1,175 @@
+use crate::{reactive_state_functions::dispose_node_with_id, runtime::Runtime, store::StorageKey};
+
+/// Keeps the callback passed to `subscribe` registered. Dropping the
+/// subscription unsubscribes.
+#[must_use = "the callback is unsubscribed as soon as the subscription is dropped"]
+pub struct Subscription {
+    pub id: StorageKey,
+    // the runtime the subscription was made in, if any
+    runtime: Option<Runtime>,
+}
+
+impl Subscription {
+    pub(crate) fn new(id: StorageKey) -> Subscription {
+        Subscription {
+            id,
+            runtime: illicit::get::<Runtime>()
+                .ok()
+                .map(|runtime| runtime.clone()),
+        }
+    }
+
+    /// Unsubscribes, the same as dropping the subscription.
+    pub fn unsubscribe(self) {}
+}
+
+impl std::fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Subscription({:#?})", self.id)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let id = self.id;
+        match &self.runtime {
+            Some(runtime) => runtime.enter(|| dispose_node_with_id(id)),
+            None => dispose_node_with_id(id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        reactive_state_access::{atom::Atom, reaction::Reaction},
+        *,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[atom]
+    fn count() -> Atom<i32> {
+        0
+    }
+
+    #[reaction]
+    fn doubled() -> Reaction<i32> {
+        count().observe() * 2
+    }
+
+    // shareable between threads, as subscribers must be with `sync`
+    type Seen<T> = Arc<Mutex<Vec<(T, T)>>>;
+
+    fn record<T: Clone + Send + 'static>() -> (Seen<T>, impl Fn(&T, &T) + Send + Sync) {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let log = seen.clone();
+        (seen, move |old: &T, new: &T| {
+            log.lock().unwrap().push((old.clone(), new.clone()))
+        })
+    }
+
+    #[test]
+    fn test_subscribe_to_atom() {
+        let (seen, callback) = record();
+        let _subscription = count().subscribe(callback);
+
+        count().set(1);
+        count().update(|c| *c += 2);
+        count().set(3);
+
+        assert_eq!(*seen.lock().unwrap(), vec![(0, 1), (1, 3)]);
+    }
+
+    #[test]
+    fn test_subscribe_to_reaction() {
+        let (seen, callback) = record();
+        let _subscription = doubled().subscribe(callback);
+
+        count().set(2);
+        assert_eq!(*seen.lock().unwrap(), vec![(0, 4)]);
+    }
+
+    #[test]
+    fn test_drop_unsubscribes() {
+        let size = || {
+            crate::reactive_state_functions::with_store(|store_refcell| {
+                store_refcell.borrow().size()
+            })
+        };
+        count();
+        let before = size();
+
+        let (seen, callback) = record();
+        let subscription = count().subscribe(callback);
+        count().set(1);
+        subscription.unsubscribe();
+        count().set(2);
+
+        assert_eq!(*seen.lock().unwrap(), vec![(0, 1)]);
+        assert_eq!(size(), before, "the subscription leaves nothing behind");
+    }
+
+    #[test]
+    fn test_subscriptions_leave_no_argument_slots() {
+        let locations = || {
+            crate::reactive_state_functions::with_store(|store_refcell| {
+                store_refcell.borrow().slotted_locations()
+            })
+        };
+        count();
+        let before = locations();
+
+        for _ in 0..3 {
+            let (_, callback) = record();
+            drop(count().subscribe(callback));
+        }
+
+        assert_eq!(locations(), before);
+    }
+
+    #[test]
+    fn test_panicking_callback_keeps_the_new_value() {
+        let (seen, callback) = record();
+        let _subscription = count().subscribe(move |old: &i32, new: &i32| {
+            callback(old, new);
+            if *new == 1 {
+                panic!("subscriber failed");
+            }
+        });
+
+        let result = std::panic::catch_unwind(|| count().set(1));
+        assert!(result.is_err());
+        count().set(2);
+
+        assert_eq!(*seen.lock().unwrap(), vec![(0, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn test_subscriptions_are_independent() {
+        let (first_seen, first) = record();
+        let (second_seen, second) = record();
+        let first = count().subscribe(first);
+        let _second = count().subscribe(second);
+
+        count().set(1);
+        drop(first);
+        count().set(2);
+
+        assert_eq!(*first_seen.lock().unwrap(), vec![(0, 1)]);
+        assert_eq!(*second_seen.lock().unwrap(), vec![(0, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn test_subscription_in_runtime() {
+        let runtime = Runtime::new();
+        let (seen, callback) = record();
+        let subscription = runtime.enter(|| count().subscribe(callback));
+
+        runtime.enter(|| count().set(5));
+        count().set(7);
+        drop(subscription);
+        runtime.enter(|| count().set(6));
+
+        assert_eq!(*seen.lock().unwrap(), vec![(0, 5)]);
+    }
+}
@@ -1,16 +1,54 @@
 use crate::{
-    reactive_state_access::{atom::Atom, reaction::Reaction, reversible_atom::ReversibleAtom},
-    reverse::global_reverse_queue,
-    store::{ReactiveContext, RxFunc, SlottedKey, StorageKey, Store},
+    error::AtomicHooksError,
+    event_log::StoreEvent,
+    graph::NodeKind,
+    probe::probe,
+    reactive_state_access::{
+        atom::Atom,
+        atom_family::{AtomFamily, FamilyMembers},
+        reaction::Reaction,
+        reversible_atom::ReversibleAtom,
+        subscription::Subscription,
+    },
+    reverse::{record_command, undo_history_for, written_values, Command, WrittenValues},
+    runtime::Runtime,
+    store::{
+        ChangeCheck, ReactiveContext, RxFunc, Storable, StorableFn, StorageKey, Store, SubscriberFn,
+    },
 };
-use std::{cell::RefCell, hash::Hash, rc::Rc};
+use std::{cell::RefCell, collections::HashSet, hash::Hash};
 
 // use seed::{*,prelude};
 
 thread_local! {
+    /// The store used when no `Runtime` has been entered. Each thread has its
+    /// own, also with the `sync` feature: share a `Runtime` to share a store
+    /// between threads.
     pub static STORE: RefCell<Store> = RefCell::new(Store::new());
 }
 
+/// Runs the closure against the store of the currently entered `Runtime`, or
+/// against the thread's default store if no runtime has been entered.
+///
+/// With the `sync` feature the store of a runtime is locked for the duration
+/// of the closure. The lock is re-entrant, so nested calls on the same thread
+/// are fine.
+pub fn with_store<F: FnOnce(&RefCell<Store>) -> R, R>(func: F) -> R {
+    if let Ok(runtime) = illicit::get::<Runtime>() {
+        runtime.with_store(func)
+    } else {
+        STORE.with(func)
+    }
+}
+
+// Holds the current store for the whole closure, so that operations made of
+// several store accesses (read-modify-write, write then propagate) cannot
+// interleave with other threads sharing a runtime when the `sync` feature is
+// enabled. The thread's default store is never shared.
+pub(crate) fn with_store_lock<F: FnOnce() -> R, R>(func: F) -> R {
+    with_store(|_| func())
+}
+
 //
 //  Constructs a T atom state accessor. T is stored keyed to the provided String
 // id.  The accessor always references this id therefore can you can set/update/
@@ -23,62 +61,120 @@ thread_local! {
 //
 // Typically this is created via the #[atom] attribute macro
 //
-pub fn atom<T: 'static, F: Fn() -> () + 'static>(id: StorageKey, data_fn: F) -> Atom<T> {
-    // we do not need to re-initalize the atom if it already has been stored.
-    if !reactive_state_exists_for_id::<T>(id) {
-        let reaction = RxFunc {
-            func: Rc::new(data_fn),
-        };
+#[track_caller]
+pub fn atom<T: Storable, F: StorableFn>(id: StorageKey, data_fn: F) -> Atom<T> {
+    let caller = std::panic::Location::caller();
+    with_store_lock(|| {
+        // we do not need to re-initalize the atom if it already has been stored.
+        if !reactive_state_exists_for_id::<T>(id) {
+            let reaction = RxFunc::new(data_fn);
 
-        STORE.with(|store_refcell| {
-            store_refcell
-                .borrow_mut()
-                .new_reaction(&id, reaction.clone());
-        });
+            with_store(|store_refcell| {
+                let mut store = store_refcell.borrow_mut();
+                store.new_reaction(&id, reaction.clone());
+                store.set_location(&id, caller.to_string());
+                store.record_value_type::<T>(&id);
+                store.set_node_kind(&id, NodeKind::Atom);
+            });
 
-        (reaction.func.clone())();
+            construct_node(id, &reaction);
 
-        STORE.with(|store_refcell| {
-            store_refcell.borrow_mut().add_atom(&id);
-        })
-    }
-    Atom::new(id)
+            with_store(|store_refcell| {
+                store_refcell.borrow_mut().add_atom(&id);
+            })
+        }
+        Atom::new(id)
+    })
 }
 
-pub fn atom_reverse<T: 'static + Clone, F: Fn() -> () + 'static>(
+/// Like `atom`, but the atom starts from the value saved under the key in the
+/// persist backend, if there is one which decodes to T, and every write is
+/// saved to the backend. Otherwise the atom starts from its default, which is
+/// not saved.
+///
+/// Typically this is created via `#[atom(persist = "key")]`.
+#[cfg(feature = "serde")]
+#[track_caller]
+pub fn atom_persisted<T, F>(id: StorageKey, key: &'static str, data_fn: F) -> Atom<T>
+where
+    T: Storable + serde::Serialize + serde::de::DeserializeOwned,
+    F: StorableFn,
+{
+    let caller = std::panic::Location::caller();
+    with_store_lock(|| {
+        if !reactive_state_exists_for_id::<T>(id) {
+            let reaction = RxFunc::new(data_fn);
+
+            with_store(|store_refcell| {
+                let mut store = store_refcell.borrow_mut();
+                store.new_reaction(&id, reaction.clone());
+                store.set_location(&id, caller.to_string());
+                store.record_value_type::<T>(&id);
+                store.set_node_kind(&id, NodeKind::Atom);
+            });
+
+            match crate::persist::load_persisted::<T>(key) {
+                Some(value) => set_inert_atom_state_with_id(value, id),
+                None => construct_node(id, &reaction),
+            }
+
+            with_store(|store_refcell| {
+                store_refcell.borrow_mut().add_atom(&id);
+            });
+            crate::persist::persist_writes::<T>(id, key);
+        }
+        Atom::new(id)
+    })
+}
+
+#[track_caller]
+pub fn atom_reverse<T: Storable + Clone, F: StorableFn>(
     id: StorageKey,
     data_fn: F,
 ) -> ReversibleAtom<T> {
-    // we do not need to re-initalize the atom if it already has been stored.
-    if !reactive_state_exists_for_id::<T>(id) {
-        let reaction = RxFunc {
-            func: Rc::new(data_fn),
-        };
+    let caller = std::panic::Location::caller();
+    with_store_lock(|| {
+        // we do not need to re-initalize the atom if it already has been stored.
+        if !reactive_state_exists_for_id::<T>(id) {
+            let reaction = RxFunc::new(data_fn);
 
-        STORE.with(|store_refcell| {
-            store_refcell
-                .borrow_mut()
-                .new_reaction(&id, reaction.clone());
-        });
+            with_store(|store_refcell| {
+                let mut store = store_refcell.borrow_mut();
+                store.new_reaction(&id, reaction.clone());
+                store.set_location(&id, caller.to_string());
+                store.record_value_type::<T>(&id);
+                store.set_node_kind(&id, NodeKind::Atom);
+            });
 
-        (reaction.func.clone())();
+            construct_node(id, &reaction);
 
-        crate::reverse::global_reverse_queue().update(|u| {
-            u.commands.push(crate::reverse::Command::new(
-                reaction,
-                RxFunc {
-                    func: Rc::new(move || {
-                        remove_reactive_state_with_id::<T>(id);
-                    }),
-                },
-            ))
-        });
+            with_store(|store_refcell| {
+                store_refcell.borrow_mut().add_atom(&id);
+            })
+        }
+        ReversibleAtom::new(id)
+    })
+}
 
-        STORE.with(|store_refcell| {
-            store_refcell.borrow_mut().add_atom(&id);
-        })
-    }
-    ReversibleAtom::new(id)
+/// Constructs the accessor of an atom family, which keeps track of the
+/// members of a parameterised atom.
+///
+/// Typically this is created via `#[atom(family)]`.
+pub fn atom_family<P: Storable + Clone + PartialEq, T: Storable>(
+    id: StorageKey,
+) -> AtomFamily<P, T> {
+    with_store_lock(|| {
+        if !reactive_state_exists_for_id::<FamilyMembers<P>>(id) {
+            with_store(|store_refcell| {
+                let mut store = store_refcell.borrow_mut();
+                store.set_state_with_id(FamilyMembers::<P>::default(), &id);
+                store.record_value_type::<FamilyMembers<P>>(&id);
+                store.set_node_kind(&id, NodeKind::Atom);
+                store.add_atom(&id);
+            });
+        }
+        AtomFamily::new(id)
+    })
 }
 
 //
@@ -93,172 +189,337 @@ pub fn atom_reverse<T: 'static + Clone, F: Fn() -> () + 'static>(
 //
 // Typically this is created via the #[reaction] attribute macro
 //
-pub fn reaction<T: 'static, F: Fn() -> () + 'static>(id: StorageKey, data_fn: F) -> Reaction<T> {
-    if !reactive_state_exists_for_id::<T>(id) {
-        STORE.with(|store_refcell| {
-            let key = store_refcell.borrow_mut().primary_slotmap.insert(id);
-
-            store_refcell.borrow_mut().id_to_key_map.insert(id, key);
-        });
+#[track_caller]
+pub fn reaction<T: Storable, F: StorableFn>(id: StorageKey, data_fn: F) -> Reaction<T> {
+    let caller = std::panic::Location::caller();
+    with_store_lock(|| {
+        if !reactive_state_exists_for_id::<T>(id) {
+            with_store(|store_refcell| store_refcell.borrow_mut().insert_node(&id));
 
-        let reaction = RxFunc {
-            func: Rc::new(data_fn),
-        };
+            let reaction = RxFunc::new(data_fn);
 
-        STORE.with(|store_refcell| {
-            store_refcell
-                .borrow_mut()
-                .new_reaction(&id, reaction.clone());
-        });
+            with_store(|store_refcell| {
+                let mut store = store_refcell.borrow_mut();
+                store.new_reaction(&id, reaction.clone());
+                store.set_location(&id, caller.to_string());
+                store.record_value_type::<T>(&id);
+                store.set_node_kind(&id, NodeKind::Reaction);
+            });
 
-        (reaction.func.clone())();
-    }
+            construct_node(id, &reaction);
+        }
 
-    Reaction::<T>::new(id)
+        Reaction::<T>::new(id)
+    })
 }
 
-pub fn reaction_start_suspended<T: 'static, F: Fn() -> () + 'static>(
+#[track_caller]
+pub fn reaction_start_suspended<T: Storable, F: StorableFn>(
     id: StorageKey,
     data_fn: F,
 ) -> Reaction<T> {
-    if !reactive_state_exists_for_id::<T>(id) {
-        STORE.with(|store_refcell| {
-            let key = store_refcell.borrow_mut().primary_slotmap.insert(id);
+    let caller = std::panic::Location::caller();
+    with_store_lock(|| {
+        if !reactive_state_exists_for_id::<T>(id) {
+            with_store(|store_refcell| store_refcell.borrow_mut().insert_node(&id));
 
-            store_refcell.borrow_mut().id_to_key_map.insert(id, key);
-        });
+            let reaction = RxFunc::new(data_fn);
 
-        let reaction = RxFunc {
-            func: Rc::new(data_fn),
-        };
+            with_store(|store_refcell| {
+                let mut store = store_refcell.borrow_mut();
+                store.new_reaction(&id, reaction.clone());
+                store.set_location(&id, caller.to_string());
+                store.record_value_type::<T>(&id);
+                store.set_node_kind(&id, NodeKind::Reaction);
+            });
+        }
 
-        STORE.with(|store_refcell| {
-            store_refcell
-                .borrow_mut()
-                .new_reaction(&id, reaction.clone());
-        });
+        Reaction::<T>::new(id)
+    })
+}
+
+/// Constructs a lazy reaction. Its body is not run until its value is first
+/// read, and changes upstream only mark it dirty: the body is re-run on the
+/// next `get`, `get_with` or `observe` rather than straight away.
+///
+/// Typically this is created via `#[reaction(lazy)]`.
+#[track_caller]
+pub fn reaction_lazy<T: Storable, F: StorableFn>(id: StorageKey, data_fn: F) -> Reaction<T> {
+    let caller = std::panic::Location::caller();
+    with_store_lock(|| {
+        let registered = with_store(|store_refcell| store_refcell.borrow().is_lazy(&id));
+        if !registered && !reactive_state_exists_for_id::<T>(id) {
+            with_store(|store_refcell| {
+                let mut store = store_refcell.borrow_mut();
+                store.insert_node(&id);
+                store.new_reaction(&id, RxFunc::new(data_fn));
+                store.set_location(&id, caller.to_string());
+                store.record_value_type::<T>(&id);
+                store.set_node_kind(&id, NodeKind::Reaction);
+                store.make_lazy(&id);
+            });
+        }
+
+        Reaction::<T>::new(id)
+    })
+}
+
+// Re-runs a lazy reaction that has not been evaluated since its inputs changed.
+fn evaluate_if_dirty(id: StorageKey) {
+    with_store_lock(|| {
+        let reaction = with_store(|store_refcell| store_refcell.borrow_mut().take_dirty(&id));
+        if let Some(reaction) = reaction {
+            with_store(|store_refcell| {
+                store_refcell
+                    .borrow_mut()
+                    .log(StoreEvent::ReactionRan { id })
+            });
+            construct_node(id, &reaction);
+        }
+    })
+}
+
+// Runs the initial evaluation of an atom or reaction, panicking with the cycle
+// path if the node's body (transitively) requires the node itself.
+fn construct_node(id: StorageKey, reaction: &RxFunc) {
+    let construction =
+        with_store(|store_refcell| store_refcell.borrow_mut().begin_construction(&id));
+    if let Err(cycle) = construction {
+        panic!("{}", cycle);
     }
 
-    Reaction::<T>::new(id)
+    let _construction = Construction(id);
+    (reaction.func.clone())();
 }
 
-pub fn unlink_dead_links(id: StorageKey) {
-    let context = illicit::get::<RefCell<ReactiveContext>>().expect(
-        "No #[reaction] context found, are you sure you are in one? I.e. does the current \
-         function have a #[reaction] tag?",
-    );
-    if reactive_state_exists_for_id::<ReactiveContext>(id) {
-        read_reactive_state_with_id::<ReactiveContext, _, ()>(id, |old_context| {
-            let ids_to_remove = old_context
-                .reactive_state_accessors
-                .iter()
-                .filter(|a_id| !context.borrow().reactive_state_accessors.contains(a_id));
-            for id_to_remove in ids_to_remove {
-                STORE.with(|store_refcell| {
-                    store_refcell
-                        .borrow_mut()
-                        .remove_dependency(id_to_remove, &id);
-                })
+// Ends the construction of a node, also if its body panics, so that the node
+// is not reported as part of a cycle afterwards.
+struct Construction(StorageKey);
+
+impl Drop for Construction {
+    fn drop(&mut self) {
+        with_store(|store_refcell| {
+            if let Ok(mut store) = store_refcell.try_borrow_mut() {
+                store.end_construction(&self.0);
             }
         })
-    } else {
-        set_inert_atom_state_with_id::<ReactiveContext>(context.borrow().clone(), id)
     }
 }
 
-/// Sets the state of type T keyed to the given TopoId
-pub fn set_inert_atom_state_with_id<T: 'static>(data: T, id: StorageKey) {
-    STORE.with(|store_refcell| store_refcell.borrow_mut().set_state_with_id::<T>(data, &id))
+pub fn unlink_dead_links(id: StorageKey) {
+    try_unlink_dead_links(id).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_unlink_dead_links(id: StorageKey) -> Result<(), AtomicHooksError> {
+    with_store_lock(|| {
+        let context = illicit::get::<RefCell<ReactiveContext>>()
+            .map_err(|_| AtomicHooksError::NoReactiveContext)?;
+        if reactive_state_exists_for_id::<ReactiveContext>(id) {
+            checked_read_reactive_state_with_id::<ReactiveContext, _, ()>(id, |old_context| {
+                let ids_to_remove = old_context
+                    .reactive_state_accessors
+                    .iter()
+                    .filter(|a_id| !context.borrow().reactive_state_accessors.contains(a_id));
+                for id_to_remove in ids_to_remove {
+                    with_store(|store_refcell| {
+                        store_refcell
+                            .borrow_mut()
+                            .remove_dependency(id_to_remove, &id);
+                    })
+                }
+            })?;
+            // the next run is compared with the dependencies of this one
+            set_inert_atom_state_with_id::<ReactiveContext>(context.borrow().clone(), id);
+            Ok(())
+        } else {
+            set_inert_atom_state_with_id::<ReactiveContext>(context.borrow().clone(), id);
+            Ok(())
+        }
+    })
 }
 
 /// Sets the state of type T keyed to the given TopoId
-pub fn set_inert_atom_reversible_state_with_id<T: 'static + Clone>(data: T, id: StorageKey) {
-    let new_data = data.clone();
-    if let Some(previous_state) = clone_reactive_state_with_id::<T>(id) {
-        global_reverse_queue().update(|u| {
-            u.commands.truncate(u.cursor);
+pub fn set_inert_atom_state_with_id<T: Storable>(data: T, id: StorageKey) {
+    with_store(|store_refcell| {
+        let mut store = store_refcell.borrow_mut();
+        if store.write_is_unchanged(&data, &id) {
+            store.mark_unchanged(&id);
+        }
+        store.set_logged_state_with_id::<T>(data, &id)
+    });
+    save_persisted_writes();
+}
 
-            u.commands.push(crate::reverse::Command::new(
+// Saves the writes of persisted atoms, which are queued while the store is
+// borrowed so that the backend and the persist error hook can access atoms.
+fn save_persisted_writes() {
+    #[cfg(feature = "serde")]
+    crate::persist::save_pending_writes();
+}
+
+/// Makes writes to the node that leave its value unchanged skip propagation.
+/// `#[atom]` and `#[reaction]` call this for every node whose type implements
+/// `PartialEq` unless they are marked `propagate_unchanged`. Writes of other
+/// types always propagate.
+pub fn skip_unchanged_writes<T: Storable + Clone + PartialEq>(id: StorageKey) {
+    set_inert_atom_state_with_id(ChangeCheck::<T>::new(), id)
+}
+
+probe!(
+    SkipUnchanged,
+    SkipWithoutEq,
+    skip_unchanged,
+    [Storable + Clone + PartialEq],
+    skip_unchanged_writes
+);
+
+fn write_is_unchanged<T: Storable>(data: &T, id: StorageKey) -> bool {
+    with_store(|store_refcell| store_refcell.borrow().write_is_unchanged(data, &id))
+}
+
+fn change_check<T: Storable>(id: StorageKey) -> Option<ChangeCheck<T>> {
+    with_store(|store_refcell| {
+        store_refcell
+            .borrow()
+            .get_state_with_id::<ChangeCheck<T>>(&id)
+            .copied()
+    })
+}
+
+/// Sets the state of type T keyed to the given TopoId
+pub fn set_inert_atom_reversible_state_with_id<T: Storable + Clone>(data: T, id: StorageKey) {
+    with_store_lock(|| {
+        let new_data = data.clone();
+        let command = if let Some(previous_state) = clone_reactive_state_with_id::<T>(id) {
+            let values = written_values(id, Some(&previous_state), Some(&data));
+            reverse_command::<T>(
+                "set",
+                values,
                 RxFunc::new(move || {
-                    set_inert_atom_state_with_id::<T>(new_data.clone(), id);
+                    set_atom_state_with_id::<T>(new_data.clone(), id);
                 }),
                 RxFunc::new(move || {
-                    set_inert_atom_state_with_id::<T>(previous_state.clone(), id);
+                    set_atom_state_with_id::<T>(previous_state.clone(), id);
                 }),
-            ));
-            u.cursor += 1;
-        })
-    } else {
-        global_reverse_queue().update(|u| {
-            u.commands.truncate(u.cursor);
-
-            u.commands.push(crate::reverse::Command::new(
+            )
+        } else {
+            let values = written_values(id, None, Some(&data));
+            reverse_command::<T>(
+                "create",
+                values,
                 RxFunc::new(move || {
-                    set_inert_atom_state_with_id::<T>(new_data.clone(), id);
+                    set_atom_state_with_id::<T>(new_data.clone(), id);
                 }),
                 RxFunc::new(move || {
                     remove_reactive_state_with_id::<T>(id);
                 }),
-            ));
-            u.cursor += 1;
-        })
-    }
+            )
+        };
 
-    STORE.with(|store_refcell| store_refcell.borrow_mut().set_state_with_id::<T>(data, &id))
+        with_store(|store_refcell| {
+            store_refcell
+                .borrow_mut()
+                .set_logged_state_with_id::<T>(data, &id)
+        });
+        save_persisted_writes();
+        record_command(undo_history_for(id), command);
+    })
 }
 
 /// Sets the state of type T keyed to the given TopoId
-pub fn set_atom_state_with_id<T: 'static>(data: T, id: StorageKey) {
-    STORE.with(|store_refcell| store_refcell.borrow_mut().set_state_with_id::<T>(data, &id));
+pub fn set_atom_state_with_id<T: Storable>(data: T, id: StorageKey) {
+    with_store_lock(|| {
+        if write_is_unchanged(&data, id) {
+            return;
+        }
+        with_store(|store_refcell| {
+            store_refcell
+                .borrow_mut()
+                .set_logged_state_with_id::<T>(data, &id)
+        });
+        save_persisted_writes();
 
-    execute_reaction_nodes(&id);
+        execute_reaction_nodes(&id);
+    })
 }
 
-/// Sets the state of type T keyed to the given TopoId
-pub fn set_atom_reversible_state_with_id<T: 'static + Clone>(data: T, id: StorageKey) {
-    let new_data = data.clone();
-    if let Some(previous_state) = clone_reactive_state_with_id::<T>(id) {
-        global_reverse_queue().update(|u| {
-            u.commands.truncate(u.cursor);
+/// Sets the state of type T keyed to the given TopoId, failing if the id
+/// holds state of another type or is being read or updated.
+pub fn try_set_atom_state_with_id<T: Storable>(
+    data: T,
+    id: StorageKey,
+) -> Result<(), AtomicHooksError> {
+    with_store_lock(|| {
+        with_store(|store_refcell| store_refcell.borrow().check_writable::<T>(&id))?;
+        set_atom_state_with_id(data, id);
+        Ok(())
+    })
+}
 
-            u.commands.push(crate::reverse::Command::new(
+/// Sets the state of type T keyed to the given TopoId
+pub fn set_atom_reversible_state_with_id<T: Storable + Clone>(data: T, id: StorageKey) {
+    with_store_lock(|| {
+        if write_is_unchanged(&data, id) {
+            return;
+        }
+        let new_data = data.clone();
+        let command = if let Some(previous_state) = clone_reactive_state_with_id::<T>(id) {
+            let values = written_values(id, Some(&previous_state), Some(&data));
+            reverse_command::<T>(
+                "set",
+                values,
                 RxFunc::new(move || {
                     set_atom_state_with_id::<T>(new_data.clone(), id);
                 }),
                 RxFunc::new(move || {
-                    set_inert_atom_state_with_id::<T>(previous_state.clone(), id);
+                    set_atom_state_with_id::<T>(previous_state.clone(), id);
                 }),
-            ));
-            u.cursor += 1;
-        })
-    } else {
-        global_reverse_queue().update(|u| {
-            u.commands.truncate(u.cursor);
-
-            u.commands.push(crate::reverse::Command::new(
+            )
+        } else {
+            let values = written_values(id, None, Some(&data));
+            reverse_command::<T>(
+                "create",
+                values,
                 RxFunc::new(move || {
                     set_atom_state_with_id::<T>(new_data.clone(), id);
                 }),
                 RxFunc::new(move || {
                     remove_reactive_state_with_id::<T>(id);
                 }),
-            ));
-            u.cursor += 1;
-        })
-    }
+            )
+        };
 
-    STORE.with(|store_refcell| store_refcell.borrow_mut().set_state_with_id::<T>(data, &id));
+        with_store(|store_refcell| {
+            store_refcell
+                .borrow_mut()
+                .set_logged_state_with_id::<T>(data, &id)
+        });
+        save_persisted_writes();
+        record_command(undo_history_for(id), command);
 
-    execute_reaction_nodes(&id);
+        execute_reaction_nodes(&id);
+    })
 }
 
-pub fn reactive_state_exists_for_id<T: 'static>(id: StorageKey) -> bool {
-    STORE.with(|store_refcell| store_refcell.borrow().state_exists_with_id::<T>(id))
+pub fn try_set_atom_reversible_state_with_id<T: Storable + Clone>(
+    data: T,
+    id: StorageKey,
+) -> Result<(), AtomicHooksError> {
+    with_store_lock(|| {
+        with_store(|store_refcell| store_refcell.borrow().check_writable::<T>(&id))?;
+        set_atom_reversible_state_with_id(data, id);
+        Ok(())
+    })
+}
+
+pub fn reactive_state_exists_for_id<T: Storable>(id: StorageKey) -> bool {
+    with_store(|store_refcell| store_refcell.borrow().state_exists_with_id::<T>(id))
 }
 
 /// Clones the state of type T keyed to the given TopoId
-pub fn clone_reactive_state_with_id<T: 'static + Clone>(id: StorageKey) -> Option<T> {
-    STORE.with(|store_refcell| {
+pub fn clone_reactive_state_with_id<T: Storable + Clone>(id: StorageKey) -> Option<T> {
+    evaluate_if_dirty(id);
+    with_store(|store_refcell| {
         store_refcell
             .borrow_mut()
             .get_state_with_id::<T>(&id)
@@ -266,123 +527,480 @@ pub fn clone_reactive_state_with_id<T: 'static + Clone>(id: StorageKey) -> Optio
     })
 }
 
-pub fn remove_reactive_state_with_id<T: 'static>(id: StorageKey) -> Option<T> {
-    STORE.with(|store_refcell| store_refcell.borrow_mut().remove_state_with_id::<T>(&id))
+pub fn try_clone_reactive_state_with_id<T: Storable + Clone>(
+    id: StorageKey,
+) -> Result<T, AtomicHooksError> {
+    evaluate_if_dirty(id);
+    with_store(|store_refcell| {
+        let store = store_refcell.borrow();
+        store
+            .get_state_with_id::<T>(&id)
+            .cloned()
+            .ok_or_else(|| store.missing_state_error::<T>(&id))
+    })
+}
+
+pub fn remove_reactive_state_with_id<T: Storable>(id: StorageKey) -> Option<T> {
+    with_store(|store_refcell| {
+        let mut store = store_refcell.borrow_mut();
+        let removed = store.remove_state_with_id::<T>(&id);
+        if removed.is_some() && store.logs_writes::<T>(&id) {
+            store.log(StoreEvent::Removed { id });
+        }
+        removed
+    })
+}
+
+/// Removes the node with the given id from the store, together with its
+/// dependency edges and everything else stored for it.
+///
+/// The reactions which observed the node are re-run straight away, or once
+/// the outermost `batch` exits, so that those still observing it create it
+/// afresh and depend on the new node.
+pub fn dispose_node_with_id(id: StorageKey) {
+    // a pending future of an async reaction is woken so that it gets dropped
+    #[cfg(not(feature = "sync"))]
+    let pending = with_store(|store_refcell| store_refcell.borrow_mut().cancel_async_task(&id));
+    #[cfg(not(feature = "sync"))]
+    if let Some(waker) = pending {
+        waker.wake();
+    }
+
+    with_store_lock(|| {
+        let (ids_reactions, dependents) = with_store(|store_refcell| {
+            let store = store_refcell.borrow();
+            (
+                store.reactions_in_height_order(&[id]),
+                store.dependent_ids(&id),
+            )
+        });
+        let batching = with_store(|store_refcell| {
+            let mut store = store_refcell.borrow_mut();
+            store.dispose(&id);
+            if store.is_batching() {
+                dependents
+                    .iter()
+                    .for_each(|dependent| store.defer_rerun(dependent));
+            }
+            store.is_batching()
+        });
+        if !batching {
+            run_stale_reactions(&ids_reactions, dependents.into_iter().collect());
+        }
+    })
 }
 
-pub fn remove_reactive_reversible_state_with_id<T: 'static + Clone>(id: StorageKey) -> Option<T> {
-    if let Some(previous_state) = clone_reactive_state_with_id::<T>(id) {
-        global_reverse_queue().update(|u| {
-            u.commands.truncate(u.cursor);
+/// Calls `callback` with the previous and the new value whenever the state
+/// with the given id changes, until the returned `Subscription` is dropped.
+///
+/// The subscription is a node of its own, depending on the state like a
+/// `#[reaction]` observing it would, and holding the value last passed to the
+/// callback.
+#[track_caller]
+pub fn subscribe_with_id<T: Storable + Clone, F: SubscriberFn<T>>(
+    source: StorageKey,
+    callback: F,
+) -> Subscription {
+    let caller = std::panic::Location::caller();
+    with_store_lock(|| {
+        let current = clone_reactive_state_with_id::<T>(source)
+            .unwrap_or_else(|| panic!("{}", AtomicHooksError::Missing { id: source }));
 
-            u.commands.push(crate::reverse::Command::new(
+        let id = with_store(|store_refcell| store_refcell.borrow_mut().new_subscription());
+        set_inert_atom_state_with_id(current, id);
+
+        let notify = move || {
+            let new_value = match clone_reactive_state_with_id::<T>(source) {
+                Some(new_value) => new_value,
+                None => return,
+            };
+            // the new value is kept before the callback runs, so that a
+            // panicking callback is still notified of the next change
+            let old_value = remove_reactive_state_with_id::<T>(id);
+            set_inert_atom_state_with_id(new_value.clone(), id);
+            if let Some(old_value) = old_value {
+                callback(&old_value, &new_value);
+            }
+        };
+
+        with_store(|store_refcell| {
+            let mut store = store_refcell.borrow_mut();
+            store.new_reaction(&id, RxFunc::new(notify));
+            store.set_node_kind(&id, NodeKind::Subscription);
+            store.set_location(&id, caller.to_string());
+            store.record_value_type::<T>(&id);
+            store.add_dependency(&source, &id)
+        })
+        .unwrap_or_else(|err| panic!("{}", err));
+
+        Subscription::new(id)
+    })
+}
+
+pub fn remove_reactive_reversible_state_with_id<T: Storable + Clone>(id: StorageKey) -> Option<T> {
+    with_store_lock(|| {
+        let command = clone_reactive_state_with_id::<T>(id).map(|previous_state| {
+            let values = written_values(id, Some(&previous_state), None);
+            reverse_command::<T>(
+                "remove",
+                values,
                 RxFunc::new(move || {
                     remove_reactive_state_with_id::<T>(id);
                 }),
                 RxFunc::new(move || {
-                    set_inert_atom_state_with_id::<T>(previous_state.clone(), id);
+                    set_atom_state_with_id::<T>(previous_state.clone(), id);
                 }),
-            ));
-            u.cursor += 1;
-        })
-    } else {
-        global_reverse_queue().update(|u| {
-            u.cursor += 1;
-        })
-    }
+            )
+        });
 
-    STORE.with(|store_refcell| store_refcell.borrow_mut().remove_state_with_id::<T>(&id))
+        let removed =
+            with_store(|store_refcell| store_refcell.borrow_mut().remove_state_with_id::<T>(&id));
+        if let Some(command) = command {
+            record_command(undo_history_for(id), command);
+        }
+        removed
+    })
 }
 
 #[derive(Clone)]
 pub struct UndoVec<T>(pub Vec<T>);
 
+// A reversible write of a T, labelled with the kind of write and the type,
+// e.g. "set i32". It is recorded in the undo history of the node's scope once
+// the write is done, so that reactions observing the history can read the
+// node.
+fn reverse_command<T: Storable>(
+    action: &str,
+    values: WrittenValues,
+    do_cmd: RxFunc,
+    reverse_cmd: RxFunc,
+) -> Command {
+    Command::new(do_cmd, reverse_cmd)
+        .with_label(format!("{} {}", action, std::any::type_name::<T>()))
+        .with_written(values)
+}
+
+/// Re-runs every reaction that (transitively) depends on the given id.
+/// Reactions are run once each, in dependency-height order, so that a reaction
+/// never observes a half-updated set of inputs. A reaction that recomputes the
+/// value it already held does not re-run its own dependents.
+///
+/// Inside a `batch` the id is only recorded and propagation happens when the
+/// outermost batch exits.
 pub fn execute_reaction_nodes(id: &StorageKey) {
-    let ids_reactions = STORE.with(|refcell_store| {
-        let mut borrow = refcell_store.borrow_mut();
-        borrow.clone_dep_funcs_for_id(id)
+    with_store_lock(|| {
+        let deferred = with_store(|refcell_store| {
+            let mut store = refcell_store.borrow_mut();
+            if store.is_batching() {
+                store.defer_propagation(id);
+                true
+            } else {
+                false
+            }
+        });
+
+        if !deferred {
+            execute_reaction_nodes_for_ids(std::slice::from_ref(id));
+        }
+    })
+}
+
+fn execute_reaction_nodes_for_ids(ids: &[StorageKey]) {
+    execute_reaction_nodes_and_reruns(ids, vec![])
+}
+
+// Propagates the writes of the ids and re-runs the given reactions, all in one
+// pass in height order.
+fn execute_reaction_nodes_and_reruns(ids: &[StorageKey], reruns: Vec<StorageKey>) {
+    let ids_reactions = with_store(|refcell_store| {
+        refcell_store
+            .borrow()
+            .reactions_in_height_order_including(ids, &reruns)
     });
 
-    for (key, reaction) in &ids_reactions {
-        let cloned_reaction = reaction.clone();
-        (cloned_reaction.func.clone())();
-        execute_reaction_nodes(&key);
+    // reactions are only re-run if something they observe has actually changed
+    let mut stale = reruns.into_iter().collect::<HashSet<_>>();
+    for id in ids {
+        stale.extend(with_store(|refcell_store| {
+            refcell_store.borrow().dependent_ids(id)
+        }));
+    }
+    run_stale_reactions(&ids_reactions, stale);
+}
+
+// Runs the reactions, in the given height order, which are stale or observe a
+// reaction whose value changed when it ran.
+fn run_stale_reactions(ids_reactions: &[(StorageKey, RxFunc)], mut stale: HashSet<StorageKey>) {
+    for (key, reaction) in ids_reactions {
+        if !stale.contains(key) {
+            continue;
+        }
+        // lazy reactions wait to be read, so treat them as changed
+        if with_store(|refcell_store| refcell_store.borrow_mut().mark_dirty(key)) {
+            stale.extend(with_store(|refcell_store| {
+                refcell_store.borrow().dependent_ids(key)
+            }));
+            continue;
+        }
+        with_store(|refcell_store| refcell_store.borrow_mut().take_unchanged(key));
+
+        with_store(|refcell_store| {
+            refcell_store
+                .borrow_mut()
+                .log(StoreEvent::ReactionRan { id: *key })
+        });
+        (reaction.func.clone())();
+
+        let unchanged = with_store(|refcell_store| refcell_store.borrow_mut().take_unchanged(key));
+        if !unchanged {
+            stale.extend(with_store(|refcell_store| {
+                refcell_store.borrow().dependent_ids(key)
+            }));
+        }
     }
 }
 
-pub fn update_atom_state_with_id<T: 'static, F: FnOnce(&mut T) -> ()>(id: StorageKey, func: F)
+/// Runs the closure with propagation deferred. Every reaction depending on
+/// state written inside the closure is run once, after the outermost batch
+/// exits.
+///
+/// If the closure panics the batch is closed and its deferred propagation is
+/// dropped, so that later writes propagate again.
+pub fn batch<F: FnOnce() -> R, R>(func: F) -> R {
+    with_store_lock(|| {
+        with_store(|refcell_store| refcell_store.borrow_mut().begin_batch());
+
+        let scope = BatchScope;
+        let result = func();
+        std::mem::forget(scope);
+
+        let (written_ids, reruns) = with_store(|refcell_store| {
+            let mut store = refcell_store.borrow_mut();
+            let written_ids = store.end_batch();
+            if store.is_batching() {
+                (written_ids, vec![])
+            } else {
+                (written_ids, store.take_batched_reruns())
+            }
+        });
+        if !written_ids.is_empty() || !reruns.is_empty() {
+            execute_reaction_nodes_and_reruns(&written_ids, reruns);
+        }
+        result
+    })
+}
+
+// Closes the batch of a `batch` whose closure panicked.
+struct BatchScope;
+
+impl Drop for BatchScope {
+    fn drop(&mut self) {
+        with_store(|refcell_store| {
+            if let Ok(mut store) = refcell_store.try_borrow_mut() {
+                store.end_batch();
+                if !store.is_batching() {
+                    store.take_batched_reruns();
+                }
+            }
+        })
+    }
+}
+
+pub fn update_atom_state_with_id<T: Storable, F: FnOnce(&mut T) -> ()>(id: StorageKey, func: F)
 where
-    T: 'static,
+    T: Storable,
 {
-    let mut item = remove_reactive_state_with_id::<T>(id)
-        .expect("You are trying to update a type state that doesnt exist in this context!");
-
-    func(&mut item);
+    try_update_atom_state_with_id(id, func).unwrap_or_else(|err| {
+        panic!(
+            "You are trying to update a type state that doesnt exist in this context! ({})",
+            err
+        )
+    })
+}
 
-    set_inert_atom_state_with_id(item, id);
+pub fn try_update_atom_state_with_id<T: Storable, F: FnOnce(&mut T)>(
+    id: StorageKey,
+    func: F,
+) -> Result<(), AtomicHooksError> {
+    with_store_lock(|| {
+        let check = change_check::<T>(id);
+        let changed = with_checked_out(id, |item: &mut T| {
+            logged_update(id, item, |item| {
+                let previous_state = check.map(|check| (check.snapshot)(item));
+                func(item);
+                match (check, previous_state) {
+                    (Some(check), Some(previous_state)) => {
+                        !(check.unchanged)(&previous_state, item)
+                    }
+                    _ => true,
+                }
+            })
+        })?;
+        save_persisted_writes();
 
-    //we need to get the associated data with this key
-    execute_reaction_nodes(&id);
+        //we need to get the associated data with this key
+        if changed {
+            execute_reaction_nodes(&id);
+        }
+        Ok(())
+    })
 }
 
-pub fn update_atom_reversible_state_with_id<T: 'static, F: FnOnce(&mut T) -> ()>(
+pub fn update_atom_reversible_state_with_id<T: Storable, F: FnOnce(&mut T) -> ()>(
     id: StorageKey,
     func: F,
 ) where
-    T: Clone + 'static,
+    T: Clone + Storable,
 {
-    let mut item = remove_reactive_state_with_id::<T>(id)
-        .expect("You are trying to update a type state that doesnt exist in this context!");
-
-    let previous_state = item.clone();
-    func(&mut item);
-
-    let new_item = item.clone();
-    global_reverse_queue().update(|u| {
-        u.commands.truncate(u.cursor);
-
-        u.commands.push(crate::reverse::Command::new(
-            RxFunc::new(move || {
-                set_inert_atom_state_with_id::<T>(new_item.clone(), id);
-            }),
-            RxFunc::new(move || {
-                set_inert_atom_state_with_id::<T>(previous_state.clone(), id);
-            }),
-        ));
-        u.cursor += 1;
-    });
+    try_update_atom_reversible_state_with_id(id, func).unwrap_or_else(|err| {
+        panic!(
+            "You are trying to update a type state that doesnt exist in this context! ({})",
+            err
+        )
+    })
+}
 
-    set_inert_atom_state_with_id(item, id);
+pub fn try_update_atom_reversible_state_with_id<T: Storable + Clone, F: FnOnce(&mut T)>(
+    id: StorageKey,
+    func: F,
+) -> Result<(), AtomicHooksError> {
+    with_store_lock(|| {
+        let check = change_check::<T>(id);
+        let mut command = None;
+        with_checked_out(id, |item: &mut T| {
+            logged_update(id, item, |item| {
+                let previous_state = item.clone();
+                func(item);
+                if let Some(check) = check {
+                    if (check.unchanged)(&previous_state, item) {
+                        return false;
+                    }
+                }
 
-    //we need to get the associated data with this key
-    execute_reaction_nodes(&id);
+                let new_item = item.clone();
+                let values = written_values(id, Some(&previous_state), Some(&*item));
+                command = Some(reverse_command::<T>(
+                    "update",
+                    values,
+                    RxFunc::new(move || {
+                        set_atom_state_with_id::<T>(new_item.clone(), id);
+                    }),
+                    RxFunc::new(move || {
+                        set_atom_state_with_id::<T>(previous_state.clone(), id);
+                    }),
+                ));
+                true
+            })
+        })?;
+        save_persisted_writes();
+
+        //we need to get the associated data with this key
+        if let Some(command) = command {
+            record_command(undo_history_for(id), command);
+            execute_reaction_nodes(&id);
+        }
+        Ok(())
+    })
 }
 
-pub fn read_reactive_state_with_id<T: 'static, F: FnOnce(&T) -> R, R>(
+pub fn read_reactive_state_with_id<T: Storable, F: FnOnce(&T) -> R, R>(
     id: StorageKey,
     func: F,
 ) -> R {
-    let item = remove_reactive_state_with_id::<T>(id)
-        .expect("You are trying to read a type state that doesnt exist in this context!");
-    let read = func(&item);
-    set_inert_atom_state_with_id(item, id);
-    read
+    checked_read_reactive_state_with_id(id, func).unwrap_or_else(|err| {
+        panic!(
+            "You are trying to read a type state that doesnt exist in this context! ({})",
+            err
+        )
+    })
 }
 
-pub fn try_read_reactive_state_with_id<T: 'static, F: FnOnce(&T) -> R, R>(
+/// Reads the state of type T keyed to the given TopoId, `None` if there is
+/// none or it is already being read or updated.
+pub fn try_read_reactive_state_with_id<T: Storable, F: FnOnce(&T) -> R, R>(
     id: StorageKey,
     func: F,
 ) -> Option<R> {
-    if let Some(item) = remove_reactive_state_with_id::<T>(id) {
-        let read = func(&item);
-        set_inert_atom_state_with_id(item, id);
-        Some(read)
-    } else {
-        None
+    checked_read_reactive_state_with_id(id, func).ok()
+}
+
+/// Like `try_read_reactive_state_with_id`, but reports why the state could
+/// not be read.
+pub fn checked_read_reactive_state_with_id<T: Storable, F: FnOnce(&T) -> R, R>(
+    id: StorageKey,
+    func: F,
+) -> Result<R, AtomicHooksError> {
+    with_store_lock(|| {
+        evaluate_if_dirty(id);
+        with_checked_out(id, |item: &mut T| func(item))
+    })
+}
+
+// Runs an update of the node's value, recording it in the event log and queueing
+// it to be saved if the node is a persisted atom, if the update returns that it
+// changed the value.
+fn logged_update<T: Storable, F: FnOnce(&mut T) -> bool>(
+    id: StorageKey,
+    item: &mut T,
+    update: F,
+) -> bool {
+    let logger = with_store(|store_refcell| store_refcell.borrow().update_logger::<T>(&id));
+    let render = |item: &T| {
+        logger
+            .flatten()
+            .map(|debug_render| (debug_render.render)(item))
+    };
+
+    let old = render(item);
+    let changed = update(item);
+    #[cfg(feature = "serde")]
+    if changed {
+        with_store(|store_refcell| store_refcell.borrow_mut().persist(item, &id));
     }
+    if changed && logger.is_some() {
+        let new = render(item);
+        with_store(|store_refcell| {
+            store_refcell
+                .borrow_mut()
+                .log(StoreEvent::Set { id, old, new })
+        });
+    }
+    changed
 }
 
-pub fn return_key_for_type_and_insert_if_required<T: 'static + Clone + Eq + Hash>(
+// Takes the state out of the store while the closure runs, so that the closure
+// is free to access the store itself, and puts it back afterwards, also if the
+// closure panics.
+pub(crate) fn with_checked_out<T: Storable, F: FnOnce(&mut T) -> R, R>(
+    id: StorageKey,
+    func: F,
+) -> Result<R, AtomicHooksError> {
+    let item = with_store(|store_refcell| store_refcell.borrow_mut().check_out::<T>(&id))?;
+    let mut checked_out = CheckedOut {
+        id,
+        item: Some(item),
+    };
+    Ok(func(checked_out.item.as_mut().unwrap()))
+}
+
+struct CheckedOut<T: Storable> {
+    id: StorageKey,
+    item: Option<T>,
+}
+
+impl<T: Storable> Drop for CheckedOut<T> {
+    fn drop(&mut self) {
+        if let Some(item) = self.item.take() {
+            with_store(|store_refcell| {
+                if let Ok(mut store) = store_refcell.try_borrow_mut() {
+                    store.check_in(item, &self.id)
+                }
+            })
+        }
+    }
+}
+
+pub fn return_key_for_type_and_insert_if_required<T: Storable + Clone + Eq + Hash>(
     value: T,
 ) -> StorageKey {
     use std::{collections::hash_map::DefaultHasher, hash::Hasher};
@@ -391,19 +1009,14 @@ pub fn return_key_for_type_and_insert_if_required<T: 'static + Clone + Eq + Hash
     value.hash(&mut hasher);
     let hash_id = hasher.finish();
 
-    let key = StorageKey::SlottedKey(SlottedKey {
-        location: hash_id,
-        slot: 0,
-    });
-
-    STORE.with(|refcell_store| {
+    with_store(|refcell_store| {
         refcell_store
             .borrow_mut()
-            .return_key_for_type_and_insert_if_required(key, value.clone())
+            .return_key_for_type_and_insert_if_required(hash_id, value)
     })
 }
 
-// fn deep_collision_check_for_state_id<T: 'static>(id:StorageKey, item:T,
+// fn deep_collision_check_for_state_id<T: Storable>(id:StorageKey, item:T,
 // stored_value:T) -> StorageKey {     if let Some() =
 // remove_reactive_state_with_id::<Vec<(T,StorageKey)>(id) {
 
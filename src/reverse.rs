@@ -1,17 +1,362 @@
 use crate::{atom::Atom, *};
 
-use store::RxFunc;
+use probe::probe;
+use reactive_state_functions::with_store;
+use std::{cell::RefCell, time::SystemTime};
+use store::{RxFunc, Storable, StorageKey};
 
+/// The history of reversible writes. The commands before `cursor` have been
+/// applied, the ones from `cursor` on have been undone and can be redone.
+///
+/// With an undo tree, commands undone before a new write are kept as a branch
+/// of the tree instead of being discarded, and `commands` holds the branch
+/// being worked on.
+///
+/// The history is unbounded unless `limits` are set, in which case the oldest
+/// commands are evicted whenever a push exceeds them.
+///
+/// Consecutive writes to the same atom are merged into one command as set by
+/// `coalescing`, which measures time with the logical `clock`.
 #[derive(Default, Clone)]
 pub struct UndoStore {
     pub commands: Vec<Command>,
     pub cursor: usize,
+    pub tree: Option<UndoTree>,
+    pub limits: UndoLimits,
+    pub coalescing: Coalescing,
+    pub clock: u64,
+    // whether the last command may absorb the next write, which it may not
+    // once the history has been travelled
+    last_open: bool,
+}
+
+/// When consecutive writes to the same atom are merged into a single command,
+/// which undoes to the value before the first write and redoes to the value
+/// after the last one.
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub enum Coalescing {
+    #[default]
+    Never,
+    /// Merges writes made at most this many ticks of the clock apart, e.g.
+    /// `Within(0)` merges the writes made between two ticks.
+    Within(u64),
+    /// Merges writes until the clock passes the tick.
+    Until(u64),
+}
+
+/// Bounds of an undo history. The size of a command is estimated from the old
+/// and new value it holds, see `SizeHint`.
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct UndoLimits {
+    pub max_commands: Option<usize>,
+    pub max_bytes: Option<usize>,
+}
+
+impl UndoStore {
+    /// Records a command which has just been applied, discarding the undone
+    /// commands unless they are kept in the undo tree.
+    pub fn push(&mut self, mut command: Command) {
+        command.tick = self.clock;
+        if self.coalesces(&command) {
+            let last = self.cursor - 1;
+            self.commands[last].absorb(command);
+            if let Some(tree) = &mut self.tree {
+                let node = tree.path[last];
+                tree.node_mut(node).command = self.commands[last].clone();
+            }
+            self.evict_to_limits();
+            return;
+        }
+
+        self.last_open = true;
+        if let Some(tree) = &mut self.tree {
+            tree.push(self.cursor, command.clone());
+        }
+        self.commands.truncate(self.cursor);
+        self.commands.push(command);
+        self.cursor += 1;
+        self.evict_to_limits();
+    }
+
+    // whether the command merges into the last one
+    fn coalesces(&self, command: &Command) -> bool {
+        let last = match self.commands.last() {
+            Some(last) if self.last_open && self.cursor == self.commands.len() => last,
+            _ => return false,
+        };
+        let within = match self.coalescing {
+            Coalescing::Never => false,
+            Coalescing::Within(ticks) => command.tick - last.tick <= ticks,
+            Coalescing::Until(tick) => command.tick <= tick,
+        };
+        within && command.node.is_some() && command.node == last.node
+    }
+
+    /// Replaces the commands of the history, e.g. with restored ones. With an
+    /// undo tree, the commands become its only branch.
+    #[cfg(feature = "serde")]
+    pub(crate) fn replace(&mut self, commands: Vec<Command>, cursor: usize) {
+        self.cursor = cursor.min(commands.len());
+        self.commands = commands;
+        self.last_open = false;
+        if self.tree.take().is_some() {
+            self.enable_tree();
+        }
+        self.evict_to_limits();
+    }
+
+    pub fn set_limits(&mut self, limits: UndoLimits) {
+        self.limits = limits;
+        self.evict_to_limits();
+    }
+
+    /// The number of commands held, and their estimated size. With an undo
+    /// tree these include the commands of every branch.
+    pub fn usage(&self) -> (usize, usize) {
+        match &self.tree {
+            Some(tree) => tree.commands().fold((0, 0), |(count, size), command| {
+                (count + 1, size + command.size)
+            }),
+            None => (
+                self.commands.len(),
+                self.commands.iter().map(|command| command.size).sum(),
+            ),
+        }
+    }
+
+    // Drops the oldest commands until the history is within its limits. If
+    // the dropped command was applied, the cursor moves with the commands.
+    fn evict_to_limits(&mut self) {
+        loop {
+            let (count, size) = self.usage();
+            let over_count = matches!(self.limits.max_commands, Some(max) if count > max);
+            let over_size = matches!(self.limits.max_bytes, Some(max) if size > max);
+            if !over_count && !over_size {
+                return;
+            }
+
+            let on_current_branch = match &mut self.tree {
+                Some(tree) => tree.evict_oldest(),
+                None => true,
+            };
+            if on_current_branch {
+                self.commands.remove(0);
+                self.cursor = self.cursor.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Starts keeping undone commands as branches. The commands recorded so
+    /// far become the first branch.
+    pub fn enable_tree(&mut self) {
+        if self.tree.is_none() {
+            let mut tree = UndoTree::default();
+            for (idx, command) in self.commands.iter().enumerate() {
+                tree.push(idx, command.clone());
+            }
+            self.tree = Some(tree);
+        }
+    }
+
+    // Moves the cursor back and returns the command to apply. Commands are
+    // run once the history is back in the store, so that they may read it,
+    // see `travel`.
+    fn undo(&mut self) -> Option<RxFunc> {
+        self.last_open = false;
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            Some(self.commands[self.cursor].reverse_cmd.clone())
+        } else {
+            None
+        }
+    }
+
+    fn redo(&mut self) -> Option<RxFunc> {
+        self.last_open = false;
+        if self.cursor < self.commands.len() {
+            self.cursor += 1;
+            Some(self.commands[self.cursor - 1].do_cmd.clone())
+        } else {
+            None
+        }
+    }
+
+    fn travel_to(&mut self, cursor: usize) -> Vec<RxFunc> {
+        let mut commands = vec![];
+        while self.cursor > cursor {
+            commands.extend(self.undo());
+        }
+        while self.cursor < cursor {
+            commands.extend(self.redo());
+        }
+        commands
+    }
+
+    fn relabel_last(&mut self, label: String) {
+        if self.cursor == 0 {
+            return;
+        }
+        if let Some(tree) = &mut self.tree {
+            let node = tree.path[self.cursor - 1];
+            tree.node_mut(node).command.label = label.clone();
+        }
+        self.commands[self.cursor - 1].label = label;
+    }
+
+    /// Makes the other branch the current one, returning the commands which
+    /// undo the current branch down to where the two fork and apply the
+    /// commands of the other branch.
+    fn switch_branch(&mut self, branch: usize) -> Vec<RxFunc> {
+        let tree = self.tree.as_ref().expect("the undo tree is not enabled");
+        assert!(
+            tree.is_leaf(branch),
+            "{} is not a branch of the undo tree",
+            branch
+        );
+        let path = tree.path_to(branch);
+        let fork = tree.shared_len(&path);
+
+        let mut commands = self.travel_to(self.cursor.min(fork));
+        let tree = self.tree.as_mut().unwrap();
+        self.commands = path
+            .iter()
+            .map(|node| tree.node(*node).command.clone())
+            .collect();
+        tree.path = path;
+        commands.extend(self.travel_to(self.commands.len()));
+        commands
+    }
+}
+
+/// Every command recorded since the tree was enabled, each one pointing to
+/// the command it was applied after. Evicted commands leave a gap, so that the
+/// ids of the remaining ones stay the same.
+#[derive(Default, Clone)]
+pub struct UndoTree {
+    nodes: Vec<Option<UndoNode>>,
+    // the nodes of the current branch, i.e. of `UndoStore::commands`
+    path: Vec<usize>,
+}
+
+#[derive(Clone)]
+struct UndoNode {
+    command: Command,
+    parent: Option<usize>,
+}
+
+impl UndoTree {
+    fn push(&mut self, cursor: usize, command: Command) {
+        self.path.truncate(cursor);
+        self.nodes.push(Some(UndoNode {
+            command,
+            parent: self.path.last().copied(),
+        }));
+        self.path.push(self.nodes.len() - 1);
+    }
+
+    fn node(&self, node: usize) -> &UndoNode {
+        self.nodes[node]
+            .as_ref()
+            .expect("undo tree node was evicted")
+    }
+
+    fn node_mut(&mut self, node: usize) -> &mut UndoNode {
+        self.nodes[node]
+            .as_mut()
+            .expect("undo tree node was evicted")
+    }
+
+    fn alive(&self) -> impl Iterator<Item = (usize, &UndoNode)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, node)| node.as_ref().map(|node| (idx, node)))
+    }
+
+    fn commands(&self) -> impl Iterator<Item = &Command> {
+        self.alive().map(|(_, node)| &node.command)
+    }
+
+    // Evicts the oldest command, which is always the root of its branches as
+    // commands are only ever added after their parent. Its children become
+    // roots themselves. Returns whether it was the first command of the
+    // current branch.
+    fn evict_oldest(&mut self) -> bool {
+        let oldest = match self.alive().next() {
+            Some((oldest, _)) => oldest,
+            None => return false,
+        };
+        self.nodes[oldest] = None;
+        for node in self.nodes.iter_mut().flatten() {
+            if node.parent == Some(oldest) {
+                node.parent = None;
+            }
+        }
+        if self.path.first() == Some(&oldest) {
+            self.path.remove(0);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn is_leaf(&self, node: usize) -> bool {
+        matches!(self.nodes.get(node), Some(Some(_)))
+            && self.alive().all(|(_, other)| other.parent != Some(node))
+    }
+
+    fn path_to(&self, node: usize) -> Vec<usize> {
+        let mut path = vec![node];
+        while let Some(parent) = self.node(*path.last().unwrap()).parent {
+            path.push(parent);
+        }
+        path.reverse();
+        path
+    }
+
+    // the number of commands the path shares with the current branch
+    fn shared_len(&self, path: &[usize]) -> usize {
+        path.iter()
+            .zip(&self.path)
+            .take_while(|(node, current)| node == current)
+            .count()
+    }
+
+    fn branches(&self) -> Vec<UndoBranch> {
+        self.alive()
+            .filter(|(node, _)| self.is_leaf(*node))
+            .map(|(node, leaf)| {
+                let path = self.path_to(node);
+                UndoBranch {
+                    id: node,
+                    len: path.len(),
+                    fork: self.shared_len(&path),
+                    label: leaf.command.label.clone(),
+                    at: leaf.command.at,
+                    current: self.path.last() == Some(&node),
+                }
+            })
+            .collect()
+    }
 }
 
 #[derive(Clone)]
 pub struct Command {
     do_cmd: RxFunc,
     reverse_cmd: RxFunc,
+    pub label: String,
+    pub at: SystemTime,
+    /// Estimated number of bytes held by the command.
+    pub size: usize,
+    // the part of the size taken by the new value
+    new_size: usize,
+    // the node written, for commands which may be coalesced
+    node: Option<StorageKey>,
+    // the clock of the history when the command was pushed
+    tick: u64,
+    // the values of the command, if it can be saved with the undo history
+    #[cfg(feature = "serde")]
+    pub(crate) saved: Option<crate::snapshot::SavedValues>,
 }
 
 impl Command {
@@ -19,19 +364,300 @@ impl Command {
         Self {
             do_cmd,
             reverse_cmd: undo_cmd,
+            label: String::new(),
+            at: SystemTime::now(),
+            size: 0,
+            new_size: 0,
+            node: None,
+            tick: 0,
+            #[cfg(feature = "serde")]
+            saved: None,
+        }
+    }
+
+    // A command applying the commands in order and undoing them in reverse
+    // order, with propagation batched so that reactions see all of the writes
+    // at once.
+    fn group(label: String, commands: Vec<Command>) -> Self {
+        let do_cmds = commands
+            .iter()
+            .map(|command| command.do_cmd.clone())
+            .collect::<Vec<_>>();
+        let reverse_cmds = commands
+            .iter()
+            .rev()
+            .map(|command| command.reverse_cmd.clone())
+            .collect::<Vec<_>>();
+        let mut group = Command::new(
+            RxFunc::new(move || batch(|| do_cmds.iter().for_each(|cmd| (cmd.func)()))),
+            RxFunc::new(move || batch(|| reverse_cmds.iter().for_each(|cmd| (cmd.func)()))),
+        )
+        .with_label(label)
+        .with_size(commands.iter().map(|command| command.size).sum());
+        group.at = commands[0].at;
+        group
+    }
+
+    pub fn with_size(mut self, size: usize) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub(crate) fn with_written(mut self, values: WrittenValues) -> Self {
+        self.size = values.old_size + values.new_size;
+        self.new_size = values.new_size;
+        self.node = values.node;
+        #[cfg(feature = "serde")]
+        {
+            self.saved = values.saved;
+        }
+        self
+    }
+
+    pub fn with_label<L: Into<String>>(mut self, label: L) -> Self {
+        self.label = label.into();
+        self
+    }
+
+    // Merges a later write of the same node into the command, keeping the
+    // label, time and old value of the command.
+    fn absorb(&mut self, next: Command) {
+        self.do_cmd = next.do_cmd;
+        self.size = self.size - self.new_size + next.new_size;
+        self.new_size = next.new_size;
+        self.tick = next.tick;
+        #[cfg(feature = "serde")]
+        {
+            self.saved = match (self.saved.take(), next.saved) {
+                (Some(saved), Some(next)) => Some(saved.followed_by(next)),
+                _ => None,
+            };
         }
     }
 }
 
-#[atom]
+/// A command of the undo history, as listed by `GlobalUndo::history`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    pub label: String,
+    /// When the command was first applied.
+    pub at: SystemTime,
+}
+
+/// A branch of the undo tree, as listed by `GlobalUndo::branches`. A branch
+/// runs from the first command of the history to one which was undone and
+/// never redone, or to the most recent command.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UndoBranch {
+    /// Identifies the branch to `GlobalUndo::switch_branch`.
+    pub id: usize,
+    /// The number of commands of the branch.
+    pub len: usize,
+    /// The number of commands the branch shares with the current one.
+    pub fork: usize,
+    /// The label of the last command of the branch.
+    pub label: String,
+    /// When the last command of the branch was first applied.
+    pub at: SystemTime,
+    pub current: bool,
+}
+
+#[atom(propagate_unchanged)]
 pub fn global_reverse_queue() -> Atom<UndoStore> {
     UndoStore::default()
 }
+
+#[atom(propagate_unchanged)]
+fn scoped_reverse_queue(name: String) -> Atom<UndoStore> {
+    UndoStore::default()
+}
+
+/// The undo history of a scope, e.g. of one document of an editor. Writes of
+/// reversible atoms are recorded in the history of their scope instead of in
+/// `global_reverse_queue`, and undoing them leaves the other scopes alone.
+///
+/// An atom is in the scope given by `#[atom(reversible, undo_scope = "name")]`
+/// or `set_undo_scope`, and otherwise in the scope of the innermost
+/// `with_undo_scope` it is written in.
+///
+/// ```
+/// use atomic_hooks::{reversible_atom::ReversibleAtom, *};
+///
+/// #[atom(reversible)]
+/// fn title(doc: u32) -> ReversibleAtom<String> {
+///     "untitled".to_string()
+/// }
+///
+/// with_undo_scope("doc-1", || title(1).set("first".to_string()));
+/// with_undo_scope("doc-2", || title(2).set("second".to_string()));
+///
+/// undo_scope("doc-1").travel_backwards();
+/// assert_eq!(title(1).get_with(|title| title.clone()), "untitled");
+/// assert_eq!(title(2).get_with(|title| title.clone()), "second");
+/// ```
+pub fn undo_scope<S: Into<String>>(name: S) -> Atom<UndoStore> {
+    scoped_reverse_queue(name.into())
+}
+
+/// Records the writes of reversible atoms made inside the closure in the
+/// history of the scope, unless the atoms have a scope of their own.
+pub fn with_undo_scope<S: Into<String>, F: FnOnce() -> R, R>(name: S, func: F) -> R {
+    illicit::Layer::new()
+        .offer(ActiveUndoScope(name.into()))
+        .enter(func)
+}
+
+/// Records the writes of the reversible atom in the history of the scope.
+pub fn set_undo_scope<S: Into<String>>(id: StorageKey, name: S) {
+    set_inert_atom_state_with_id(UndoScopeName(name.into()), id)
+}
+
+#[derive(Debug)]
+struct ActiveUndoScope(String);
+
+#[derive(Clone)]
+struct UndoScopeName(String);
+
+/// The history writes of the node are recorded in.
+pub(crate) fn undo_history_for(id: StorageKey) -> Atom<UndoStore> {
+    let scope = with_store(|store_refcell| {
+        store_refcell
+            .borrow()
+            .get_state_with_id::<UndoScopeName>(&id)
+            .map(|scope| scope.0.clone())
+    })
+    .or_else(|| {
+        illicit::get::<ActiveUndoScope>()
+            .ok()
+            .map(|scope| scope.0.clone())
+    });
+    match scope {
+        Some(scope) => undo_scope(scope),
+        None => global_reverse_queue(),
+    }
+}
+
+/// Records the reversible writes made inside the closure as a single command
+/// in each history they are recorded in, so that they are undone and redone
+/// together. A group inside another one becomes one command of the outer
+/// group.
+///
+/// Groups are not part of saved undo histories, see `save_history`.
+///
+/// ```
+/// use atomic_hooks::{reversible_atom::ReversibleAtom, *};
+///
+/// #[atom(reversible)]
+/// fn left(shape: u32) -> ReversibleAtom<i32> {
+///     shape as i32 * 10
+/// }
+///
+/// left(1);
+/// left(2);
+/// undo_group("Align", || {
+///     left(1).set(0);
+///     left(2).set(0);
+/// });
+/// assert_eq!(global_reverse_queue().history().last().unwrap().label, "Align");
+///
+/// global_reverse_queue().travel_backwards();
+/// assert_eq!(left(1).get_with(|left| *left), 10);
+/// assert_eq!(left(2).get_with(|left| *left), 20);
+/// ```
+pub fn undo_group<L: Into<String>, F: FnOnce() -> R, R>(label: L, func: F) -> R {
+    let (result, recorded) = illicit::Layer::new()
+        .offer(OpenUndoGroup::default())
+        .enter(|| {
+            let result = func();
+            let recorded = illicit::expect::<OpenUndoGroup>().0.take();
+            (result, recorded)
+        });
+
+    let label = label.into();
+    for (history, commands) in recorded {
+        record_command(history, Command::group(label.clone(), commands));
+    }
+    result
+}
+
+// The commands recorded in the innermost `undo_group`, by history.
+#[derive(Default)]
+struct OpenUndoGroup(RefCell<Vec<(Atom<UndoStore>, Vec<Command>)>>);
+
+impl std::fmt::Debug for OpenUndoGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("OpenUndoGroup")
+            .field(&self.0.borrow().len())
+            .finish()
+    }
+}
+
+/// Pushes the command onto the history, or adds it to the innermost open
+/// `undo_group`.
+pub(crate) fn record_command(history: Atom<UndoStore>, command: Command) {
+    match illicit::get::<OpenUndoGroup>() {
+        Ok(group) => {
+            let mut recorded = group.0.borrow_mut();
+            match recorded
+                .iter_mut()
+                .find(|(other, _)| other.id == history.id)
+            {
+                Some((_, commands)) => commands.push(command),
+                None => recorded.push((history, vec![command])),
+            }
+        }
+        Err(_) => history.update(|u| u.push(command)),
+    }
+}
+/// Travelling the history writes the values of atoms like any other write,
+/// except that the reactions depending on them are run once the travel is
+/// over, e.g. once after `travel_to_cursor` undid several commands.
 pub trait GlobalUndo {
     fn travel_backwards(&self);
     fn travel_forwards(&self);
     fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Undoes or redoes commands until `cursor` commands are applied.
+    /// Panics if `cursor` is greater than the length of the history.
     fn travel_to_cursor(&self, cursor: usize);
+    /// The number of applied commands.
+    fn cursor(&self) -> usize;
+    /// The commands of the history, oldest first.
+    fn history(&self) -> Vec<HistoryEntry>;
+    /// Replaces the label of the most recently applied command, e.g. with a
+    /// description of the action which caused the write.
+    fn label_last<L: Into<String>>(&self, label: L);
+    /// Keeps the commands undone before a write as a branch of an undo tree,
+    /// like Vim does, instead of discarding them.
+    fn enable_undo_tree(&self);
+    /// The branches of the undo tree, in the order they were started. Empty
+    /// unless the undo tree is enabled.
+    fn branches(&self) -> Vec<UndoBranch>;
+    /// Makes the branch the current one, undoing the commands of the current
+    /// branch down to where the two fork and applying the commands of the
+    /// other branch. The history then holds the commands of the branch.
+    ///
+    /// Panics if the undo tree is not enabled or `branch` is not the id of one
+    /// of its branches.
+    fn switch_branch(&self, branch: usize);
+    /// Bounds the history, evicting its oldest commands right away if it
+    /// exceeds the new limits.
+    fn set_limits(&self, limits: UndoLimits);
+    /// Sets when consecutive writes to the same atom are merged into one
+    /// command.
+    fn set_coalescing(&self, coalescing: Coalescing);
+    /// Merges consecutive writes to the same atom until the clock passes the
+    /// tick, e.g. `history.coalesce_until(history.clock())` merges the writes
+    /// made until the next `tick`.
+    fn coalesce_until(&self, tick: u64) {
+        self.set_coalescing(Coalescing::Until(tick))
+    }
+    /// Advances the logical clock coalescing is measured with, e.g. once per
+    /// input event.
+    fn tick(&self);
+    fn clock(&self) -> u64;
 }
 
 impl GlobalUndo for Atom<UndoStore> {
@@ -40,43 +666,673 @@ impl GlobalUndo for Atom<UndoStore> {
     }
 
     fn travel_to_cursor(&self, cursor: usize) {
-        assert!(cursor > 0);
-        assert!(cursor < self.len());
-
-        update_atom_state_with_id::<UndoStore, _>(self.id, |queue| {
-            if cursor > queue.cursor {
-                while cursor < queue.cursor {
-                    if queue.cursor > 0 {
-                        (queue.commands[queue.cursor - 1].reverse_cmd.func)();
-                        queue.cursor -= 1;
-                    }
-                }
-            } else if cursor < queue.cursor {
-                while cursor < queue.cursor {
-                    if queue.cursor < queue.commands.len() {
-                        (queue.commands[queue.cursor].do_cmd.func)();
-                        queue.cursor += 1;
-                    }
-                }
-            }
+        let len = self.len();
+        assert!(
+            cursor <= len,
+            "cannot travel to {} in an undo history of {} commands",
+            cursor,
+            len
+        );
+
+        travel(*self, |queue| queue.travel_to(cursor))
+    }
+
+    fn cursor(&self) -> usize {
+        read_reactive_state_with_id::<UndoStore, _, _>(self.id, |q| q.cursor)
+    }
+
+    fn history(&self) -> Vec<HistoryEntry> {
+        read_reactive_state_with_id::<UndoStore, _, _>(self.id, |q| {
+            q.commands
+                .iter()
+                .map(|command| HistoryEntry {
+                    label: command.label.clone(),
+                    at: command.at,
+                })
+                .collect()
+        })
+    }
+
+    fn label_last<L: Into<String>>(&self, label: L) {
+        update_atom_state_with_id::<UndoStore, _>(self.id, |queue| queue.relabel_last(label.into()))
+    }
+
+    fn enable_undo_tree(&self) {
+        update_atom_state_with_id::<UndoStore, _>(self.id, |queue| queue.enable_tree())
+    }
+
+    fn branches(&self) -> Vec<UndoBranch> {
+        read_reactive_state_with_id::<UndoStore, _, _>(self.id, |q| {
+            q.tree
+                .as_ref()
+                .map(|tree| tree.branches())
+                .unwrap_or_default()
         })
     }
 
+    fn switch_branch(&self, branch: usize) {
+        travel(*self, |queue| queue.switch_branch(branch))
+    }
+
+    fn set_limits(&self, limits: UndoLimits) {
+        update_atom_state_with_id::<UndoStore, _>(self.id, |queue| queue.set_limits(limits))
+    }
+
+    fn set_coalescing(&self, coalescing: Coalescing) {
+        update_atom_state_with_id::<UndoStore, _>(self.id, |queue| queue.coalescing = coalescing)
+    }
+
+    fn tick(&self) {
+        update_atom_state_with_id::<UndoStore, _>(self.id, |queue| queue.clock += 1)
+    }
+
+    fn clock(&self) -> u64 {
+        read_reactive_state_with_id::<UndoStore, _, _>(self.id, |q| q.clock)
+    }
+
     fn travel_backwards(&self) {
-        update_atom_state_with_id::<UndoStore, _>(self.id, |queue| {
-            if queue.cursor > 0 {
-                (queue.commands[queue.cursor - 1].reverse_cmd.func)();
-                queue.cursor -= 1;
-            }
-        });
+        travel(*self, |queue| queue.undo().into_iter().collect())
     }
 
     fn travel_forwards(&self) {
-        update_atom_state_with_id::<UndoStore, _>(self.id, |queue| {
-            if queue.cursor < queue.commands.len() {
-                (queue.commands[queue.cursor].do_cmd.func)();
-                queue.cursor += 1;
+        travel(*self, |queue| queue.redo().into_iter().collect())
+    }
+}
+
+// Moves the cursor of the history and then applies the commands passed over,
+// as one batch so that reactions observing both the history and the atoms
+// written run once with all of the changes.
+fn travel<F: FnOnce(&mut UndoStore) -> Vec<RxFunc>>(history: Atom<UndoStore>, func: F) {
+    batch(|| {
+        let mut commands = vec![];
+        update_atom_state_with_id::<UndoStore, _>(history.id, |queue| commands = func(queue));
+        for command in commands {
+            (command.func)();
+        }
+    })
+}
+
+/// Estimates the number of bytes a value holds, including what it owns on the
+/// heap, to bound the undo history by size. The values of reversible atoms
+/// whose type does not implement it are estimated by `std::mem::size_of`.
+pub trait SizeHint {
+    fn size_hint(&self) -> usize;
+}
+
+macro_rules! size_of_hint {
+    ($($t:ty),*) => {
+        $(impl SizeHint for $t {
+            fn size_hint(&self) -> usize {
+                std::mem::size_of::<$t>()
+            }
+        })*
+    };
+}
+
+size_of_hint!(bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+impl SizeHint for String {
+    fn size_hint(&self) -> usize {
+        std::mem::size_of::<String>() + self.capacity()
+    }
+}
+
+impl<T: SizeHint> SizeHint for Vec<T> {
+    fn size_hint(&self) -> usize {
+        std::mem::size_of::<Vec<T>>()
+            + (self.capacity() - self.len()) * std::mem::size_of::<T>()
+            + self.iter().map(SizeHint::size_hint).sum::<usize>()
+    }
+}
+
+impl<T: SizeHint> SizeHint for Option<T> {
+    fn size_hint(&self) -> usize {
+        match self {
+            Some(value) => {
+                std::mem::size_of::<Option<T>>() - std::mem::size_of::<T>() + value.size_hint()
             }
+            None => std::mem::size_of::<Option<T>>(),
+        }
+    }
+}
+
+/// Estimates the size of the values of a node in the undo history.
+pub struct UndoSize<T> {
+    size: fn(&T) -> usize,
+}
+
+impl<T> Clone for UndoSize<T> {
+    fn clone(&self) -> UndoSize<T> {
+        *self
+    }
+}
+
+impl<T> Copy for UndoSize<T> {}
+
+/// Estimates the values of the node with `SizeHint` in the undo history.
+pub fn hint_undo_size<T: Storable + SizeHint>(id: StorageKey) {
+    set_inert_atom_state_with_id(
+        UndoSize::<T> {
+            size: SizeHint::size_hint,
+        },
+        id,
+    )
+}
+
+/// What the undo history keeps of the values of a write.
+pub(crate) struct WrittenValues {
+    // only writes replacing a value are coalesced
+    node: Option<StorageKey>,
+    old_size: usize,
+    new_size: usize,
+    #[cfg(feature = "serde")]
+    saved: Option<crate::snapshot::SavedValues>,
+}
+
+/// The values of a write of the node, `None` if the node has no value before
+/// or after it.
+pub(crate) fn written_values<T: Storable>(
+    id: StorageKey,
+    old: Option<&T>,
+    new: Option<&T>,
+) -> WrittenValues {
+    WrittenValues {
+        node: (old.is_some() && new.is_some()).then_some(id),
+        old_size: old.map_or(0, |value| undo_size(id, value)),
+        new_size: new.map_or(0, |value| undo_size(id, value)),
+        #[cfg(feature = "serde")]
+        saved: crate::snapshot::saved_values(id, old, new),
+    }
+}
+
+// the estimated size of a value of the node in the undo history
+fn undo_size<T: Storable>(id: StorageKey, value: &T) -> usize {
+    let size = with_store(|store_refcell| {
+        store_refcell
+            .borrow()
+            .get_state_with_id::<UndoSize<T>>(&id)
+            .copied()
+    });
+    match size {
+        Some(size) => (size.size)(value),
+        None => std::mem::size_of::<T>(),
+    }
+}
+
+probe!(
+    HintSize,
+    HintWithoutSize,
+    hint_size,
+    [Storable + SizeHint],
+    hint_undo_size
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reactive_state_access::{
+        reaction::Reaction, reversible_atom::ReversibleAtom, CloneReactiveState,
+    };
+
+    #[atom(reversible)]
+    fn width() -> ReversibleAtom<u32> {
+        0
+    }
+
+    #[atom(reversible)]
+    fn title() -> ReversibleAtom<String> {
+        "untitled".to_string()
+    }
+
+    #[atom(reversible, undo_scope = "sidebar")]
+    fn sidebar_width() -> ReversibleAtom<u32> {
+        200
+    }
+
+    #[reaction]
+    fn area() -> Reaction<u32> {
+        width().observe() * sidebar_width().observe()
+    }
+
+    #[reaction]
+    fn undo_status() -> Reaction<String> {
+        let cursor = global_reverse_queue().observe_with(|history| history.cursor);
+        format!("{} at {}", width().observe(), cursor)
+    }
+
+    fn area_runs() -> usize {
+        event_log_for(area().id)
+            .into_iter()
+            .filter(|logged| logged.event == StoreEvent::ReactionRan { id: area().id })
+            .count()
+    }
+
+    fn labels() -> Vec<String> {
+        global_reverse_queue()
+            .history()
+            .into_iter()
+            .map(|entry| entry.label)
+            .collect()
+    }
+
+    // the labels up to the type name default labels end with, whose
+    // rendering is not stable
+    fn actions() -> Vec<String> {
+        labels()
+            .into_iter()
+            .map(|label| label.split(' ').next().unwrap_or_default().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_history_lists_labelled_commands() {
+        width().set(1);
+        global_reverse_queue().label_last("Resize");
+        title().set("draft".to_string());
+        width().update(|width| *width += 1);
+        global_reverse_queue().label_last("Grow");
+
+        assert_eq!(actions(), vec!["create", "Resize", "create", "set", "Grow"]);
+        assert_eq!(global_reverse_queue().cursor(), 5);
+
+        let history = global_reverse_queue().history();
+        assert!(history.windows(2).all(|pair| pair[0].at <= pair[1].at));
+    }
+
+    #[test]
+    fn test_travel_to_cursor() {
+        // creating the atom is the first command
+        for value in 1..=4 {
+            width().set(value);
+        }
+
+        global_reverse_queue().travel_to_cursor(2);
+        assert_eq!(width().get(), 1);
+        assert_eq!(global_reverse_queue().cursor(), 2);
+
+        global_reverse_queue().travel_to_cursor(5);
+        assert_eq!(width().get(), 4);
+
+        global_reverse_queue().travel_to_cursor(1);
+        assert_eq!(width().get(), 0);
+        assert_eq!(global_reverse_queue().len(), 5);
+
+        global_reverse_queue().travel_to_cursor(4);
+        assert_eq!(width().get(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot travel to 4 in an undo history of 3 commands")]
+    fn test_travel_beyond_history() {
+        width().set(1);
+        width().set(2);
+        global_reverse_queue().travel_to_cursor(4);
+    }
+
+    #[test]
+    fn test_write_after_undo_discards_redo() {
+        width().set(1);
+        width().set(2);
+        global_reverse_queue().travel_backwards();
+
+        title().set("draft".to_string());
+        assert_eq!(actions(), vec!["create", "set", "create", "set"]);
+        assert_eq!(global_reverse_queue().cursor(), 4);
+
+        global_reverse_queue().travel_forwards();
+        assert_eq!(width().get(), 1);
+    }
+
+    #[test]
+    fn test_undo_tree_keeps_abandoned_branches() {
+        global_reverse_queue().enable_undo_tree();
+        for value in 1..=3 {
+            width().set(value);
+        }
+        global_reverse_queue().travel_to_cursor(2);
+        width().set(10);
+
+        let branches = global_reverse_queue().branches();
+        assert_eq!(branches.len(), 2);
+        assert_eq!((branches[0].len, branches[0].fork), (4, 2));
+        assert!(!branches[0].current);
+        assert_eq!((branches[1].len, branches[1].fork), (3, 3));
+        assert!(branches[1].current);
+
+        global_reverse_queue().switch_branch(branches[0].id);
+        assert_eq!(width().get(), 3);
+        assert_eq!(global_reverse_queue().cursor(), 4);
+        global_reverse_queue().travel_backwards();
+        assert_eq!(width().get(), 2);
+
+        global_reverse_queue().switch_branch(branches[1].id);
+        assert_eq!(width().get(), 10);
+        global_reverse_queue().travel_backwards();
+        assert_eq!(width().get(), 1);
+    }
+
+    #[test]
+    fn test_undo_tree_branches_from_branches() {
+        width().set(1);
+        global_reverse_queue().enable_undo_tree();
+        width().set(2);
+        global_reverse_queue().travel_backwards();
+        width().set(3);
+        global_reverse_queue().travel_backwards();
+        global_reverse_queue().travel_backwards();
+        title().set("draft".to_string());
+
+        let branches = global_reverse_queue().branches();
+        assert_eq!(branches.len(), 3);
+        global_reverse_queue().switch_branch(branches[1].id);
+        assert_eq!((width().get(), title().get()), (3, "untitled".to_string()));
+
+        global_reverse_queue().switch_branch(branches[0].id);
+        assert_eq!(width().get(), 2);
+        assert!(global_reverse_queue().branches()[0].current);
+    }
+
+    #[test]
+    fn test_without_undo_tree_writes_discard_redo() {
+        width().set(1);
+        global_reverse_queue().travel_backwards();
+        width().set(2);
+        assert!(global_reverse_queue().branches().is_empty());
+    }
+
+    #[test]
+    fn test_history_is_bounded_by_count() {
+        global_reverse_queue().set_limits(UndoLimits {
+            max_commands: Some(3),
+            max_bytes: None,
+        });
+        for value in 1..=5 {
+            width().set(value);
+        }
+
+        assert_eq!(global_reverse_queue().len(), 3);
+        assert_eq!(global_reverse_queue().cursor(), 3);
+        global_reverse_queue().travel_to_cursor(0);
+        assert_eq!(width().get(), 2);
+    }
+
+    #[test]
+    fn test_history_is_bounded_by_size() {
+        let text = |len: usize| "x".repeat(len);
+        title().set(text(100));
+        title().set(text(200));
+        let size = global_reverse_queue().get_with(|queue| queue.usage().1);
+        assert!(size > 500, "strings are estimated by their capacity");
+
+        global_reverse_queue().set_limits(UndoLimits {
+            max_commands: None,
+            max_bytes: Some(700),
+        });
+        title().set(text(300));
+        assert!(global_reverse_queue().get_with(|queue| queue.usage().1) <= 700);
+        assert_eq!(global_reverse_queue().len(), 1);
+
+        global_reverse_queue().travel_backwards();
+        assert_eq!(title().get().len(), 200);
+    }
+
+    #[test]
+    fn test_eviction_keeps_undone_commands_redoable() {
+        for value in 1..=3 {
+            width().set(value);
+        }
+        global_reverse_queue().travel_backwards();
+
+        global_reverse_queue().set_limits(UndoLimits {
+            max_commands: Some(2),
+            max_bytes: None,
         });
+        assert_eq!(global_reverse_queue().cursor(), 1);
+        global_reverse_queue().travel_forwards();
+        assert_eq!(width().get(), 3);
+    }
+
+    #[test]
+    fn test_eviction_from_undo_tree() {
+        global_reverse_queue().enable_undo_tree();
+        width().set(1);
+        width().set(2);
+        global_reverse_queue().travel_backwards();
+        width().set(3);
+
+        global_reverse_queue().set_limits(UndoLimits {
+            max_commands: Some(2),
+            max_bytes: None,
+        });
+        assert_eq!(global_reverse_queue().len(), 1);
+        let branches = global_reverse_queue().branches();
+        assert_eq!(branches.len(), 2);
+
+        global_reverse_queue().switch_branch(branches[0].id);
+        assert_eq!(width().get(), 2);
+    }
+
+    #[test]
+    fn test_undo_scopes_are_independent() {
+        with_undo_scope("doc", || width().set(1));
+        title().set("global".to_string());
+        sidebar_width().set(300);
+
+        assert_eq!(undo_scope("doc").len(), 2);
+        assert_eq!(undo_scope("sidebar").len(), 2);
+        assert_eq!(global_reverse_queue().len(), 2);
+
+        undo_scope("doc").travel_backwards();
+        assert_eq!(width().get(), 0);
+        assert_eq!(title().get(), "global");
+        assert_eq!(sidebar_width().get(), 300);
+
+        global_reverse_queue().travel_backwards();
+        assert_eq!(title().get(), "untitled");
+        assert_eq!(sidebar_width().get(), 300);
+    }
+
+    #[test]
+    fn test_atom_scope_wins_over_active_scope() {
+        with_undo_scope("doc", || {
+            sidebar_width().set(250);
+            with_undo_scope("inner", || width().set(5));
+        });
+
+        assert!(undo_scope("doc").is_empty());
+        assert_eq!(undo_scope("sidebar").len(), 2);
+        assert_eq!(undo_scope("inner").len(), 2);
+    }
+
+    fn title_history() -> (usize, String) {
+        (global_reverse_queue().len(), title().get())
+    }
+
+    #[test]
+    fn test_coalescing_merges_consecutive_writes() {
+        title();
+        global_reverse_queue().set_coalescing(Coalescing::Within(0));
+        for text in ["d", "dr", "dra"] {
+            title().set(text.to_string());
+        }
+        assert_eq!(title_history(), (2, "dra".to_string()));
+
+        width().set(1);
+        title().set("draft".to_string());
+        assert_eq!(global_reverse_queue().len(), 5);
+
+        global_reverse_queue().travel_to_cursor(2);
+        assert_eq!(title().get(), "dra");
+        global_reverse_queue().travel_backwards();
+        assert_eq!(title().get(), "untitled");
+    }
+
+    #[test]
+    fn test_coalescing_window_is_measured_in_ticks() {
+        title();
+        global_reverse_queue().set_coalescing(Coalescing::Within(1));
+        title().set("a".to_string());
+        global_reverse_queue().tick();
+        title().set("ab".to_string());
+        global_reverse_queue().tick();
+        global_reverse_queue().tick();
+        title().set("abc".to_string());
+
+        assert_eq!(title_history(), (3, "abc".to_string()));
+        global_reverse_queue().travel_backwards();
+        assert_eq!(title().get(), "ab");
+    }
+
+    #[test]
+    fn test_coalesce_until() {
+        title();
+        let history = global_reverse_queue();
+        history.coalesce_until(history.clock() + 1);
+        title().set("a".to_string());
+        history.tick();
+        title().set("ab".to_string());
+        history.tick();
+        title().set("abc".to_string());
+
+        assert_eq!(title_history(), (3, "abc".to_string()));
+        history.travel_backwards();
+        assert_eq!(title().get(), "ab");
+        history.travel_backwards();
+        assert_eq!(title().get(), "untitled");
+    }
+
+    #[test]
+    fn test_travelling_ends_coalescing() {
+        title();
+        global_reverse_queue().set_coalescing(Coalescing::Within(0));
+        title().set("a".to_string());
+        title().set("ab".to_string());
+        global_reverse_queue().travel_backwards();
+        global_reverse_queue().travel_forwards();
+        title().set("abc".to_string());
+
+        assert_eq!(title_history(), (3, "abc".to_string()));
+    }
+
+    #[test]
+    fn test_undo_group_is_one_command() {
+        width();
+        title();
+        undo_group("Resize", || {
+            width().set(10);
+            width().set(20);
+            title().set("wide".to_string());
+        });
+
+        assert_eq!(labels().last().unwrap(), "Resize");
+        assert_eq!(global_reverse_queue().len(), 3);
+
+        global_reverse_queue().travel_backwards();
+        assert_eq!((width().get(), title().get()), (0, "untitled".to_string()));
+        global_reverse_queue().travel_forwards();
+        assert_eq!((width().get(), title().get()), (20, "wide".to_string()));
+    }
+
+    #[test]
+    fn test_nested_undo_groups() {
+        width();
+        let answer = undo_group("Outer", || {
+            width().set(1);
+            undo_group("Inner", || {
+                width().set(2);
+                title().set("nested".to_string());
+            });
+            width().set(3);
+            42
+        });
+
+        assert_eq!(answer, 42);
+        assert_eq!(actions(), vec!["create", "Outer"]);
+
+        global_reverse_queue().travel_backwards();
+        assert_eq!(width().get(), 0);
+        assert!(global_reverse_queue().get_with(|queue| queue
+            .commands
+            .iter()
+            .all(|command| command.label != "Inner")));
+        global_reverse_queue().travel_forwards();
+        assert_eq!((width().get(), title().get()), (3, "nested".to_string()));
+    }
+
+    #[test]
+    fn test_undo_group_without_writes_records_nothing() {
+        width();
+        undo_group("Nothing", || width().get());
+        assert_eq!(global_reverse_queue().len(), 1);
+    }
+
+    #[test]
+    fn test_undo_group_spans_scopes() {
+        width();
+        sidebar_width();
+        undo_group("Layout", || {
+            width().set(100);
+            sidebar_width().set(300);
+        });
+
+        assert_eq!(labels().last().unwrap(), "Layout");
+        assert_eq!(
+            undo_scope("sidebar").history().last().unwrap().label,
+            "Layout"
+        );
+
+        undo_scope("sidebar").travel_backwards();
+        assert_eq!((width().get(), sidebar_width().get()), (100, 200));
+    }
+
+    #[test]
+    fn test_travelling_reruns_reactions() {
+        width().set(2);
+        width().update(|width| *width += 1);
+        sidebar_width().remove();
+        assert_eq!(area().get(), 600);
+
+        undo_scope("sidebar").travel_backwards();
+        assert_eq!(area().get(), 600);
+        global_reverse_queue().travel_backwards();
+        assert_eq!(area().get(), 400);
+        global_reverse_queue().travel_backwards();
+        assert_eq!(area().get(), 0);
+        global_reverse_queue().travel_forwards();
+        assert_eq!(area().get(), 400);
+    }
+
+    #[test]
+    fn test_travelling_runs_reactions_once() {
+        area();
+        for value in 1..=3 {
+            width().set(value);
+        }
+        enable_event_log(100);
+
+        global_reverse_queue().travel_to_cursor(1);
+        assert_eq!((area().get(), area_runs()), (0, 1));
+
+        undo_group("Twice", || {
+            width().set(2);
+            width().set(3);
+        });
+        clear_event_log();
+        global_reverse_queue().travel_backwards();
+        assert_eq!((area().get(), area_runs()), (0, 1));
+    }
+
+    #[test]
+    fn test_reaction_observing_history_and_atom() {
+        undo_status();
+        width().set(5);
+        assert_eq!(undo_status().get(), "5 at 2");
+        width().update(|width| *width += 1);
+        assert_eq!(undo_status().get(), "6 at 3");
+
+        global_reverse_queue().travel_backwards();
+        assert_eq!(undo_status().get(), "5 at 2");
+        global_reverse_queue().travel_to_cursor(3);
+        assert_eq!(undo_status().get(), "6 at 3");
     }
 }
@@ -0,0 +1,201 @@
+use crate::{reactive_state_functions::batch, store::Store};
+use std::cell::RefCell;
+#[cfg(not(feature = "sync"))]
+use std::rc::Rc;
+#[cfg(feature = "sync")]
+use std::sync::Arc;
+
+/// A handle to an isolated store of atoms, reactions and hook state.
+///
+/// Accessors such as `Atom`, `Reaction`, `ReversibleAtom` and `StateAccess`
+/// resolve against the runtime that is currently entered, falling back to
+/// the thread's default store when none is. Handles are cheap to clone and
+/// the store is dropped together with the last handle.
+///
+/// With the `sync` feature a runtime can be sent to and entered from other
+/// threads; each access locks its store, and a write keeps it locked while the
+/// reactions it triggers run. The default stores are per thread and never
+/// shared: threads share state only by entering the same runtime, e.g. a
+/// clone of it moved into each thread.
+///
+/// ```
+/// use atomic_hooks::Runtime;
+///
+/// let first = Runtime::new();
+/// let second = Runtime::new();
+///
+/// first.enter(|| {
+///     // atoms created or written here live in `first` only
+/// });
+/// second.enter(|| {
+///     // and these in `second` only
+/// });
+/// ```
+#[derive(Clone)]
+pub struct Runtime {
+    #[cfg(not(feature = "sync"))]
+    store: Rc<RefCell<Store>>,
+    #[cfg(feature = "sync")]
+    store: Arc<parking_lot::ReentrantMutex<RefCell<Store>>>,
+}
+
+impl Runtime {
+    pub fn new() -> Runtime {
+        Runtime {
+            #[cfg(not(feature = "sync"))]
+            store: Rc::new(RefCell::new(Store::new())),
+            #[cfg(feature = "sync")]
+            store: Arc::new(parking_lot::ReentrantMutex::new(RefCell::new(Store::new()))),
+        }
+    }
+
+    /// Runs the closure with this runtime as the current store.
+    pub fn enter<F: FnOnce() -> R, R>(&self, func: F) -> R {
+        illicit::Layer::new().offer(self.clone()).enter(func)
+    }
+
+    /// Enters this runtime and runs the closure as a single `batch`.
+    pub fn batch<F: FnOnce() -> R, R>(&self, func: F) -> R {
+        self.enter(|| batch(func))
+    }
+
+    pub(crate) fn with_store<F: FnOnce(&RefCell<Store>) -> R, R>(&self, func: F) -> R {
+        #[cfg(not(feature = "sync"))]
+        {
+            func(&self.store)
+        }
+        #[cfg(feature = "sync")]
+        {
+            func(&self.store.lock())
+        }
+    }
+}
+
+impl Default for Runtime {
+    fn default() -> Runtime {
+        Runtime::new()
+    }
+}
+
+impl std::fmt::Debug for Runtime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        #[cfg(not(feature = "sync"))]
+        let ptr = Rc::as_ptr(&self.store);
+        #[cfg(feature = "sync")]
+        let ptr = Arc::as_ptr(&self.store);
+        write!(f, "Runtime({:p})", ptr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        reactive_state_access::{atom::Atom, reaction::Reaction, CloneReactiveState},
+        *,
+    };
+
+    #[atom]
+    fn count() -> Atom<i32> {
+        0
+    }
+
+    #[reaction]
+    fn doubled() -> Reaction<i32> {
+        count().observe() * 2
+    }
+
+    #[cfg(feature = "sync")]
+    #[atom]
+    fn shared_count() -> Atom<i32> {
+        0
+    }
+
+    #[test]
+    fn test_runtimes_are_isolated() {
+        let first = Runtime::new();
+        let second = Runtime::new();
+
+        first.enter(|| count().set(5));
+        second.enter(|| count().set(7));
+
+        assert_eq!(first.enter(|| doubled().get()), 10);
+        assert_eq!(second.enter(|| doubled().get()), 14);
+        assert_eq!(count().get(), 0, "the default store is untouched");
+    }
+
+    #[test]
+    fn test_reactions_run_in_their_runtime() {
+        let runtime = Runtime::new();
+        let doubled = runtime.enter(doubled);
+
+        runtime.enter(|| count().set(3));
+        Runtime::new().enter(|| count().set(100));
+
+        assert_eq!(runtime.enter(|| doubled.get()), 6);
+    }
+
+    #[test]
+    fn test_dropped_runtime_starts_fresh() {
+        let runtime = Runtime::new();
+        runtime.enter(|| count().set(9));
+        drop(runtime);
+
+        let runtime = Runtime::new();
+        assert_eq!(runtime.enter(|| count().get()), 0);
+    }
+
+    #[test]
+    fn test_runtime_batch() {
+        let runtime = Runtime::new();
+        let doubled = runtime.enter(doubled);
+
+        runtime.batch(|| {
+            count().set(1);
+            count().update(|c| *c += 1);
+            assert_eq!(doubled.get(), 0);
+        });
+
+        assert_eq!(runtime.enter(|| doubled.get()), 4);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn test_runtime_is_shared_between_threads() {
+        let runtime = Runtime::new();
+        let doubled = runtime.enter(doubled);
+
+        let workers = (0..4)
+            .map(|_| {
+                let runtime = runtime.clone();
+                std::thread::spawn(move || {
+                    runtime.enter(|| {
+                        for _ in 0..100 {
+                            count().update(|c| *c += 1);
+                        }
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert_eq!(runtime.enter(|| count().get()), 400);
+        assert_eq!(runtime.enter(|| doubled.get()), 800);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn test_default_store_is_per_thread() {
+        let seen = std::thread::spawn(|| {
+            shared_count().set(42);
+            shared_count().get()
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(seen, 42);
+        assert_eq!(shared_count().get(), 0);
+    }
+}
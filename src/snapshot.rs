@@ -0,0 +1,657 @@
+use crate::{
+    atom::Atom,
+    error::AtomicHooksError,
+    reactive_state_functions::{
+        batch, read_reactive_state_with_id, remove_reactive_state_with_id, set_atom_state_with_id,
+        set_inert_atom_state_with_id, try_read_reactive_state_with_id, update_atom_state_with_id,
+        with_store,
+    },
+    reverse::{Command, UndoStore},
+    store::{RxFunc, Storable, StorageKey},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::SystemTime,
+};
+
+/// The values of the atoms marked `#[atom(serialize)]`, keyed by the path of
+/// the atom function and, for atoms taking parameters, the parameters.
+///
+/// ```
+/// use atomic_hooks::{atom::Atom, *};
+///
+/// #[atom(serialize)]
+/// fn name() -> Atom<String> {
+///     "".to_string()
+/// }
+///
+/// name().set("saved".to_string());
+/// let json = snapshot().unwrap().to_json();
+///
+/// name().set("changed".to_string());
+/// restore(&StoreSnapshot::from_json(&json).unwrap()).unwrap();
+/// assert_eq!(name().get_with(|name| name.clone()), "saved");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StoreSnapshot {
+    pub atoms: BTreeMap<String, Value>,
+}
+
+impl StoreSnapshot {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("a snapshot only holds JSON values")
+    }
+
+    pub fn from_json(json: &str) -> Result<StoreSnapshot, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct SnapshotAtom {
+    id: StorageKey,
+    save: fn(StorageKey) -> Option<Result<Value, serde_json::Error>>,
+    load: fn(StorageKey, Value) -> Result<(), serde_json::Error>,
+    remove: fn(StorageKey),
+}
+
+/// The serializable atoms of a store, together with the restored values of
+/// atoms which have not been created yet.
+#[derive(Default)]
+pub(crate) struct SnapshotRegistry {
+    atoms: HashMap<String, SnapshotAtom>,
+    pending: HashMap<String, Value>,
+}
+
+impl SnapshotRegistry {
+    pub(crate) fn forget(&mut self, id: &StorageKey) {
+        self.atoms.retain(|_, atom| atom.id != *id);
+    }
+}
+
+fn save<T: Storable + Serialize>(id: StorageKey) -> Option<Result<Value, serde_json::Error>> {
+    try_read_reactive_state_with_id::<T, _, _>(id, |value| serde_json::to_value(value))
+}
+
+fn load<T: Storable + DeserializeOwned>(
+    id: StorageKey,
+    value: Value,
+) -> Result<(), serde_json::Error> {
+    set_atom_state_with_id(serde_json::from_value::<T>(value)?, id);
+    Ok(())
+}
+
+fn remove<T: Storable>(id: StorageKey) {
+    remove_reactive_state_with_id::<T>(id);
+}
+
+/// The key an atom is snapshot under. Used by the code `#[atom(serialize)]`
+/// generates.
+#[doc(hidden)]
+pub fn snapshot_key<A: Serialize>(path: &str, args: &A) -> String {
+    match serde_json::to_value(args) {
+        Ok(Value::Null) => path.to_string(),
+        Ok(args) => format!("{}{}", path, args),
+        Err(err) => panic!("the parameters of {} cannot be serialized: {}", path, err),
+    }
+}
+
+/// Makes a newly created atom part of snapshots, and gives it its restored
+/// value if `restore` was called before it was created. A restored value that
+/// no longer deserializes is dropped. Called by the code `#[atom(serialize)]`
+/// generates.
+#[doc(hidden)]
+pub fn serializable_atom<T: Storable + Serialize + DeserializeOwned>(id: StorageKey, key: String) {
+    let pending = with_store(|store_refcell| {
+        let mut store = store_refcell.borrow_mut();
+        store.snapshots.atoms.insert(
+            key.clone(),
+            SnapshotAtom {
+                id,
+                save: save::<T>,
+                load: load::<T>,
+                remove: remove::<T>,
+            },
+        );
+        store.snapshots.pending.remove(&key)
+    });
+    if let Some(value) = pending {
+        let _ = load::<T>(id, value);
+    }
+}
+
+/// Captures the values of the serializable atoms of the current store.
+///
+/// Fails if a value cannot be serialized, e.g. because it is a map whose keys
+/// are not strings.
+pub fn snapshot() -> Result<StoreSnapshot, AtomicHooksError> {
+    let atoms = with_store(|store_refcell| {
+        store_refcell
+            .borrow()
+            .snapshots
+            .atoms
+            .iter()
+            .map(|(key, atom)| (key.clone(), *atom))
+            .collect::<Vec<_>>()
+    });
+
+    let mut snapshot = StoreSnapshot::default();
+    for (key, atom) in atoms {
+        match (atom.save)(atom.id) {
+            Some(Ok(value)) => {
+                snapshot.atoms.insert(key, value);
+            }
+            Some(Err(err)) => {
+                return Err(AtomicHooksError::Serialize {
+                    key,
+                    message: err.to_string(),
+                })
+            }
+            None => {}
+        }
+    }
+    Ok(snapshot)
+}
+
+/// Sets the serializable atoms of the current store to their values in the
+/// snapshot, as a single `batch`. Reactions observing the restored atoms run
+/// once afterwards.
+///
+/// Atoms which do not exist yet take their value from the snapshot when they
+/// are created. Fails on the first value which does not deserialize to the
+/// type of its atom, leaving the values restored so far in place.
+pub fn restore(snapshot: &StoreSnapshot) -> Result<(), serde_json::Error> {
+    batch(|| {
+        for (key, value) in &snapshot.atoms {
+            let atom = with_store(|store_refcell| {
+                let mut store = store_refcell.borrow_mut();
+                let atom = store.snapshots.atoms.get(key).copied();
+                if atom.is_none() {
+                    store.snapshots.pending.insert(key.clone(), value.clone());
+                }
+                atom
+            });
+            if let Some(atom) = atom {
+                (atom.load)(atom.id, value.clone())?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// The commands of an undo history which can be saved, i.e. the writes of
+/// atoms marked `#[atom(reversible, serialize)]`, together with the number of
+/// applied ones.
+///
+/// ```
+/// use atomic_hooks::{reversible_atom::ReversibleAtom, *};
+///
+/// #[atom(reversible, serialize)]
+/// fn name() -> ReversibleAtom<String> {
+///     "".to_string()
+/// }
+///
+/// name().set("first".to_string());
+/// name().set("second".to_string());
+/// let values = snapshot().unwrap().to_json();
+/// let history = save_history(global_reverse_queue()).to_json();
+///
+/// let next_session = Runtime::new();
+/// next_session.enter(|| {
+///     name();
+///     restore(&StoreSnapshot::from_json(&values).unwrap()).unwrap();
+///     restore_history(global_reverse_queue(), &SavedHistory::from_json(&history).unwrap());
+///
+///     global_reverse_queue().travel_backwards();
+///     assert_eq!(name().get_with(|name| name.clone()), "first");
+/// });
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SavedHistory {
+    pub commands: Vec<SavedCommand>,
+    pub cursor: usize,
+    /// The commands of the history which could not be saved, see
+    /// `save_history`. Not part of the JSON.
+    #[serde(skip)]
+    pub omitted: Vec<OmittedCommand>,
+}
+
+impl SavedHistory {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("a saved history only holds JSON values")
+    }
+
+    pub fn from_json(json: &str) -> Result<SavedHistory, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+/// A write of an atom, identified by its snapshot key. A missing value means
+/// that the atom had no value before or after the write.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedCommand {
+    pub label: String,
+    pub at: SystemTime,
+    pub size: usize,
+    pub atom: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+/// A command left out of a saved history, identified by its position in the
+/// history it was saved from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OmittedCommand {
+    pub index: usize,
+    pub label: String,
+    pub at: SystemTime,
+}
+
+/// The serialized values of a write.
+#[derive(Clone)]
+pub(crate) struct SavedValues {
+    atom: String,
+    old: Option<Value>,
+    new: Option<Value>,
+}
+
+impl SavedValues {
+    /// The values of the write followed by a later write of the same atom.
+    pub(crate) fn followed_by(self, next: SavedValues) -> SavedValues {
+        SavedValues {
+            new: next.new,
+            ..self
+        }
+    }
+}
+
+// Serializes the values of a reversible atom for its undo history. The atom
+// is registered before it is created so that the initial write is included.
+struct UndoEncoder<T> {
+    key: String,
+    encode: fn(&T) -> Option<Value>,
+}
+
+impl<T> Clone for UndoEncoder<T> {
+    fn clone(&self) -> UndoEncoder<T> {
+        UndoEncoder {
+            key: self.key.clone(),
+            encode: self.encode,
+        }
+    }
+}
+
+/// Makes the writes of a reversible atom part of saved undo histories. Called
+/// by the code `#[atom(reversible, serialize)]` generates.
+#[doc(hidden)]
+pub fn serializable_undo_values<T: Storable + Serialize>(id: StorageKey, key: String) {
+    set_inert_atom_state_with_id(
+        UndoEncoder::<T> {
+            key,
+            encode: |value| serde_json::to_value(value).ok(),
+        },
+        id,
+    )
+}
+
+/// The values of a write of the node, `None` if the node is not part of saved
+/// undo histories or a value fails to serialize.
+pub(crate) fn saved_values<T: Storable>(
+    id: StorageKey,
+    old: Option<&T>,
+    new: Option<&T>,
+) -> Option<SavedValues> {
+    let encoder = with_store(|store_refcell| {
+        store_refcell
+            .borrow()
+            .get_state_with_id::<UndoEncoder<T>>(&id)
+            .cloned()
+    })?;
+    let encode = |value: Option<&T>| match value {
+        Some(value) => (encoder.encode)(value).map(Some),
+        None => Some(None),
+    };
+    Some(SavedValues {
+        old: encode(old)?,
+        new: encode(new)?,
+        atom: encoder.key,
+    })
+}
+
+/// Captures the history for saving. Commands which cannot be saved, i.e.
+/// writes of atoms which are not `serialize` and undo groups, are left out
+/// together with every command before them, as are the branches of an undo
+/// tree other than the current one. The commands left out are listed in
+/// `omitted`, and the cursor counts from the first saved command.
+pub fn save_history(history: Atom<UndoStore>) -> SavedHistory {
+    read_reactive_state_with_id::<UndoStore, _, _>(history.id, |queue| {
+        let first = queue
+            .commands
+            .iter()
+            .rposition(|command| command.saved.is_none())
+            .map_or(0, |idx| idx + 1);
+        SavedHistory {
+            commands: queue.commands[first..]
+                .iter()
+                .map(|command| {
+                    let saved = command.saved.clone().unwrap();
+                    SavedCommand {
+                        label: command.label.clone(),
+                        at: command.at,
+                        size: command.size,
+                        atom: saved.atom,
+                        old: saved.old,
+                        new: saved.new,
+                    }
+                })
+                .collect(),
+            cursor: queue.cursor.saturating_sub(first),
+            omitted: queue.commands[..first]
+                .iter()
+                .enumerate()
+                .map(|(index, command)| OmittedCommand {
+                    index,
+                    label: command.label.clone(),
+                    at: command.at,
+                })
+                .collect(),
+        }
+    })
+}
+
+/// Replaces the commands of the history with saved ones, without changing the
+/// value of any atom. Restore the values the history was saved with first, see
+/// `restore`, and create the atoms it refers to beforehand, as creating a
+/// reversible atom is recorded in its history.
+///
+/// A command of an atom which does not exist when the command is undone or
+/// redone gives the atom its value once it is created, like `restore` does.
+/// Values which no longer deserialize are skipped.
+pub fn restore_history(history: Atom<UndoStore>, saved: &SavedHistory) {
+    let commands = saved
+        .commands
+        .iter()
+        .map(|saved| {
+            let (atom, new) = (saved.atom.clone(), saved.new.clone());
+            let do_cmd = RxFunc::new(move || apply_saved(&atom, &new));
+            let (atom, old) = (saved.atom.clone(), saved.old.clone());
+            let reverse_cmd = RxFunc::new(move || apply_saved(&atom, &old));
+
+            let mut command = Command::new(do_cmd, reverse_cmd)
+                .with_label(saved.label.clone())
+                .with_size(saved.size);
+            command.at = saved.at;
+            command.saved = Some(SavedValues {
+                atom: saved.atom.clone(),
+                old: saved.old.clone(),
+                new: saved.new.clone(),
+            });
+            command
+        })
+        .collect();
+
+    update_atom_state_with_id::<UndoStore, _>(history.id, |queue| {
+        queue.replace(commands, saved.cursor)
+    })
+}
+
+fn apply_saved(key: &str, value: &Option<Value>) {
+    let atom = with_store(|store_refcell| {
+        let mut store = store_refcell.borrow_mut();
+        let atom = store.snapshots.atoms.get(key).copied();
+        if atom.is_none() {
+            match value {
+                Some(value) => store
+                    .snapshots
+                    .pending
+                    .insert(key.to_string(), value.clone()),
+                None => store.snapshots.pending.remove(key),
+            };
+        }
+        atom
+    });
+    if let Some(atom) = atom {
+        match value {
+            Some(value) => {
+                let _ = (atom.load)(atom.id, value.clone());
+            }
+            None => (atom.remove)(atom.id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        reactive_state_access::{
+            atom::Atom, reaction::Reaction, reversible_atom::ReversibleAtom, CloneReactiveState,
+        },
+        *,
+    };
+    use std::cell::Cell;
+
+    thread_local! {
+        static TOTAL_RUNS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Todo {
+        title: String,
+        done: bool,
+    }
+
+    #[atom(serialize)]
+    fn todo(id: u32) -> Atom<Todo> {
+        Todo {
+            title: format!("todo {}", id),
+            done: false,
+        }
+    }
+
+    #[atom(serialize)]
+    fn filter() -> Atom<String> {
+        "all".to_string()
+    }
+
+    #[atom]
+    fn scroll_position() -> Atom<u32> {
+        0
+    }
+
+    #[atom(reversible, serialize)]
+    fn zoom() -> ReversibleAtom<u32> {
+        100
+    }
+
+    #[atom(reversible)]
+    fn caret() -> ReversibleAtom<u32> {
+        0
+    }
+
+    #[atom(serialize)]
+    fn cells() -> Atom<HashMap<(u32, u32), String>> {
+        HashMap::new()
+    }
+
+    #[reaction]
+    fn total() -> Reaction<String> {
+        TOTAL_RUNS.with(|runs| runs.set(runs.get() + 1));
+        format!("{} {}", filter().observe(), todo(1).observe().title)
+    }
+
+    #[test]
+    fn test_snapshot_holds_serializable_atoms() {
+        todo(1);
+        filter();
+        scroll_position().set(10);
+
+        let keys = snapshot()
+            .unwrap()
+            .atoms
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                "atomic_hooks::snapshot::test::filter".to_string(),
+                "atomic_hooks::snapshot::test::todo[1]".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_snapshot_fails_for_unserializable_values() {
+        cells().update(|cells| {
+            cells.insert((1, 2), "b1".to_string());
+        });
+
+        match snapshot() {
+            Err(AtomicHooksError::Serialize { key, .. }) => {
+                assert_eq!(key, "atomic_hooks::snapshot::test::cells")
+            }
+            other => panic!("expected a serialization error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_snapshot_round_trips_through_json() {
+        todo(1).update(|todo| todo.done = true);
+        todo(2);
+        filter().set("done".to_string());
+
+        let saved = snapshot().unwrap();
+        let json = saved.to_json();
+        todo(1).set(todo(2).get());
+        filter().set("all".to_string());
+
+        let restored = StoreSnapshot::from_json(&json).unwrap();
+        assert_eq!(restored, saved);
+        restore(&restored).unwrap();
+        assert!(todo(1).get().done);
+        assert_eq!(filter().get(), "done");
+    }
+
+    #[test]
+    fn test_restore_runs_reactions_once() {
+        let total = total();
+        let saved = Runtime::new().enter(|| {
+            filter().set("open".to_string());
+            todo(1).update(|todo| todo.title = "first".to_string());
+            snapshot().unwrap()
+        });
+        let runs = TOTAL_RUNS.with(Cell::get);
+
+        restore(&saved).unwrap();
+        assert_eq!(total.get(), "open first");
+        assert_eq!(TOTAL_RUNS.with(Cell::get), runs + 1);
+    }
+
+    #[test]
+    fn test_restore_before_atoms_exist() {
+        let saved = Runtime::new().enter(|| {
+            todo(3).update(|todo| todo.done = true);
+            snapshot().unwrap()
+        });
+
+        let session = Runtime::new();
+        session.enter(|| restore(&saved)).unwrap();
+        assert!(session.enter(|| todo(3).get().done));
+        assert!(!session.enter(|| todo(4).get().done));
+    }
+
+    #[test]
+    fn test_restore_fails_for_mismatched_values() {
+        filter();
+        let mut saved = snapshot().unwrap();
+        saved.atoms.insert(
+            "atomic_hooks::snapshot::test::filter".to_string(),
+            Value::Bool(true),
+        );
+
+        assert!(restore(&saved).is_err());
+        assert_eq!(filter().get(), "all");
+    }
+
+    #[test]
+    fn test_history_round_trips_through_json() {
+        zoom().set(150);
+        zoom().set(200);
+        let values = snapshot().unwrap();
+        let saved = save_history(global_reverse_queue());
+        assert_eq!((saved.commands.len(), saved.cursor), (3, 3));
+        assert_eq!(SavedHistory::from_json(&saved.to_json()).unwrap(), saved);
+
+        Runtime::new().enter(|| {
+            zoom();
+            restore(&values).unwrap();
+            restore_history(global_reverse_queue(), &saved);
+            assert_eq!(global_reverse_queue().len(), 3);
+
+            global_reverse_queue().travel_backwards();
+            assert_eq!(zoom().get(), 150);
+            global_reverse_queue().travel_to_cursor(1);
+            assert_eq!(zoom().get(), 100);
+            global_reverse_queue().travel_forwards();
+            global_reverse_queue().travel_forwards();
+            assert_eq!(zoom().get(), 200);
+        });
+    }
+
+    #[test]
+    fn test_history_is_saved_from_the_last_unsaveable_command() {
+        zoom().set(150);
+        caret().set(3);
+        zoom().set(200);
+        global_reverse_queue().travel_backwards();
+
+        let saved = save_history(global_reverse_queue());
+        assert_eq!(saved.commands.len(), 1);
+        assert_eq!(saved.cursor, 0);
+        assert_eq!(saved.commands[0].old, Some(Value::from(150)));
+        assert_eq!(saved.commands[0].new, Some(Value::from(200)));
+        assert_eq!(
+            saved
+                .omitted
+                .iter()
+                .map(|omitted| omitted.index)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+        assert!(SavedHistory::from_json(&saved.to_json())
+            .unwrap()
+            .omitted
+            .is_empty());
+    }
+
+    #[test]
+    fn test_undo_groups_are_reported_as_omitted() {
+        zoom();
+        undo_group("Zoom in", || zoom().set(150));
+        zoom().set(200);
+
+        let saved = save_history(global_reverse_queue());
+        assert_eq!(saved.commands.len(), 1);
+        assert_eq!(saved.omitted.len(), 2);
+        assert_eq!(saved.omitted[1].label, "Zoom in");
+    }
+
+    #[test]
+    fn test_restored_commands_apply_to_atoms_created_later() {
+        let saved = Runtime::new().enter(|| {
+            zoom().set(150);
+            zoom().set(200);
+            save_history(global_reverse_queue())
+        });
+
+        let session = Runtime::new();
+        session.enter(|| {
+            restore_history(global_reverse_queue(), &saved);
+            global_reverse_queue().travel_to_cursor(2);
+            assert_eq!(zoom().get(), 150);
+        });
+    }
+}
@@ -1,10 +1,66 @@
 use anymap::any::Any;
 use slotmap::{DefaultKey, DenseSlotMap, Key, SecondaryMap};
-use std::collections::HashMap;
 pub use std::collections::HashSet;
+use std::collections::{HashMap, VecDeque};
 // use seed::*;
-use crate::*;
-use std::{hash::Hash, rc::Rc};
+use crate::{
+    error::{AtomicHooksError, CycleNode, DependencyCycle},
+    event_log::{DebugRender, EventLog, LoggedEvent, StoreEvent},
+    graph::{DependencyGraph, GraphNode, NodeKind},
+    *,
+};
+#[cfg(not(feature = "sync"))]
+use std::rc::Rc;
+#[cfg(any(feature = "sync", feature = "serde"))]
+use std::sync::Arc;
+use std::{any::TypeId, hash::Hash};
+
+/// Bound for every value kept in a `Store`.
+///
+/// With the `sync` feature the store is shared between threads, so stored
+/// values must also be `Send + Sync`.
+#[cfg(not(feature = "sync"))]
+pub trait Storable: 'static {}
+#[cfg(not(feature = "sync"))]
+impl<T: 'static> Storable for T {}
+
+#[cfg(feature = "sync")]
+pub trait Storable: Send + Sync + 'static {}
+#[cfg(feature = "sync")]
+impl<T: Send + Sync + 'static> Storable for T {}
+
+/// Bound for closures kept in a `Store`, such as reaction bodies.
+#[cfg(not(feature = "sync"))]
+pub trait StorableFn: Fn() -> () + 'static {}
+#[cfg(not(feature = "sync"))]
+impl<F: Fn() -> () + 'static> StorableFn for F {}
+
+#[cfg(feature = "sync")]
+pub trait StorableFn: Fn() -> () + Send + Sync + 'static {}
+#[cfg(feature = "sync")]
+impl<F: Fn() -> () + Send + Sync + 'static> StorableFn for F {}
+
+/// Bound for the callbacks passed to `subscribe`.
+#[cfg(not(feature = "sync"))]
+pub trait SubscriberFn<T>: Fn(&T, &T) + 'static {}
+#[cfg(not(feature = "sync"))]
+impl<T, F: Fn(&T, &T) + 'static> SubscriberFn<T> for F {}
+
+#[cfg(feature = "sync")]
+pub trait SubscriberFn<T>: Fn(&T, &T) + Send + Sync + 'static {}
+#[cfg(feature = "sync")]
+impl<T, F: Fn(&T, &T) + Send + Sync + 'static> SubscriberFn<T> for F {}
+
+#[cfg(not(feature = "sync"))]
+type StoredAny = dyn Any;
+#[cfg(feature = "sync")]
+type StoredAny = dyn Any + Send + Sync;
+
+// Argument tuples of parameterised atoms and reactions.
+#[cfg(not(feature = "sync"))]
+type StoredArgs = dyn std::any::Any;
+#[cfg(feature = "sync")]
+type StoredArgs = dyn std::any::Any + Send + Sync;
 
 #[derive(Debug, Clone)]
 pub struct ReactiveContext {
@@ -33,6 +89,20 @@ pub enum StorageKey {
 pub struct SlottedKey {
     pub location: u64,
     pub slot: u64,
+    /// How many nodes held the slot before, so that handles to a disposed node
+    /// do not refer to the node given its slot afterwards.
+    pub generation: u64,
+}
+
+// The location subscription keys are numbered in, which no argument tuple
+// is expected to hash to.
+const SUBSCRIPTION_LOCATION: u64 = u64::MAX;
+
+// The argument tuple of the node holding a slot, if any.
+#[derive(Default)]
+struct ArgSlot {
+    args: Option<Box<StoredArgs>>,
+    generation: u64,
 }
 
 #[derive(Clone, Copy, Eq, PartialEq, Debug, Hash)]
@@ -41,15 +111,97 @@ pub struct TopoKey {
     pub id: topo::CallId,
 }
 
+/// Type of the value held by an atom, reaction or hook state, recorded when
+/// it is created so that typed accessors can report mismatches.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ValueType {
+    id: TypeId,
+    name: &'static str,
+}
+
+/// Compares a node's new value with its previous one, so that writes which
+/// leave the value unchanged do not propagate to dependents.
+pub struct ChangeCheck<T> {
+    pub(crate) snapshot: fn(&T) -> T,
+    pub(crate) unchanged: fn(&T, &T) -> bool,
+}
+
+impl<T> Clone for ChangeCheck<T> {
+    fn clone(&self) -> ChangeCheck<T> {
+        *self
+    }
+}
+
+impl<T> Copy for ChangeCheck<T> {}
+
+impl<T: Clone + PartialEq> ChangeCheck<T> {
+    pub fn new() -> ChangeCheck<T> {
+        ChangeCheck {
+            snapshot: T::clone,
+            unchanged: T::eq,
+        }
+    }
+}
+
+impl<T: Clone + PartialEq> Default for ChangeCheck<T> {
+    fn default() -> ChangeCheck<T> {
+        ChangeCheck::new()
+    }
+}
+
+/// Source location of the `#[atom]` or `#[reaction]` that created a node.
+#[derive(Clone, Debug)]
+pub struct NodeLocation(pub String);
+
 #[derive(Clone)]
 pub struct RxFunc {
+    #[cfg(not(feature = "sync"))]
     pub func: Rc<dyn Fn() -> () + 'static>,
+    #[cfg(feature = "sync")]
+    pub func: Arc<dyn Fn() -> () + Send + Sync + 'static>,
 }
 
 impl RxFunc {
-    pub fn new<F: Fn() -> () + 'static>(func: F) -> Self {
+    pub fn new<F: StorableFn>(func: F) -> Self {
         RxFunc {
+            #[cfg(not(feature = "sync"))]
             func: Rc::new(func),
+            #[cfg(feature = "sync")]
+            func: Arc::new(func),
+        }
+    }
+}
+
+// The future most recently started by an async reaction. Futures of earlier
+// generations are stale and are dropped the next time they are polled.
+#[cfg(not(feature = "sync"))]
+#[derive(Default)]
+struct AsyncTask {
+    generation: u64,
+    waker: Option<std::task::Waker>,
+}
+
+// Operations on one of the store's secondary maps that do not need to know
+// the type of the values it holds.
+#[derive(Clone, Copy)]
+struct SecondaryMapOps {
+    remove: fn(&mut anymap::Map<StoredAny>, DefaultKey),
+    len: fn(&anymap::Map<StoredAny>) -> usize,
+}
+
+impl SecondaryMapOps {
+    fn of<T: Storable>() -> SecondaryMapOps {
+        SecondaryMapOps {
+            remove: |anymap, key| {
+                if let Some(map) = anymap.get_mut::<SecondaryMap<DefaultKey, T>>() {
+                    map.remove(key);
+                }
+            },
+            len: |anymap| {
+                anymap
+                    .get::<SecondaryMap<DefaultKey, T>>()
+                    .map_or(0, |map| map.len())
+            },
         }
     }
 }
@@ -57,8 +209,31 @@ impl RxFunc {
 pub struct Store {
     pub id_to_key_map: HashMap<StorageKey, DefaultKey>,
     pub primary_slotmap: DenseSlotMap<DefaultKey, StorageKey>,
-    pub anymap: anymap::Map<dyn Any>,
+    pub anymap: anymap::Map<StoredAny>,
     pub unseen_ids: HashSet<TopoKey>,
+    batch_depth: usize,
+    subscriptions: u64,
+    event_log: Option<EventLog>,
+    #[cfg(feature = "serde")]
+    pub(crate) snapshots: crate::snapshot::SnapshotRegistry,
+    #[cfg(feature = "serde")]
+    persist_backend: Option<Arc<dyn crate::persist::PersistBackend>>,
+    #[cfg(feature = "serde")]
+    persist_error_hook: Option<Arc<dyn crate::persist::PersistErrorFn>>,
+    #[cfg(feature = "serde")]
+    pending_saves: Vec<crate::persist::PendingSave>,
+    batched_sources: Vec<StorageKey>,
+    batched_reruns: Vec<StorageKey>,
+    under_construction: Vec<StorageKey>,
+    unchanged_outputs: HashSet<StorageKey>,
+    lazy_reactions: HashMap<StorageKey, bool>,
+    #[cfg(not(feature = "sync"))]
+    async_tasks: HashMap<StorageKey, AsyncTask>,
+    #[cfg(not(feature = "sync"))]
+    executor: Option<Rc<dyn crate::loadable::Executor>>,
+    checked_out: HashSet<(StorageKey, TypeId)>,
+    slotted_args: HashMap<u64, Vec<ArgSlot>>,
+    secondary_maps: Vec<SecondaryMapOps>,
 }
 
 impl Store {
@@ -68,18 +243,98 @@ impl Store {
             primary_slotmap: DenseSlotMap::new(),
             anymap: anymap::Map::new(),
             unseen_ids: HashSet::new(),
+            batch_depth: 0,
+            subscriptions: 0,
+            event_log: None,
+            #[cfg(feature = "serde")]
+            snapshots: Default::default(),
+            #[cfg(feature = "serde")]
+            persist_backend: None,
+            #[cfg(feature = "serde")]
+            persist_error_hook: None,
+            #[cfg(feature = "serde")]
+            pending_saves: vec![],
+            batched_sources: vec![],
+            batched_reruns: vec![],
+            under_construction: vec![],
+            unchanged_outputs: HashSet::new(),
+            lazy_reactions: HashMap::new(),
+            #[cfg(not(feature = "sync"))]
+            async_tasks: HashMap::new(),
+            #[cfg(not(feature = "sync"))]
+            executor: None,
+            checked_out: HashSet::new(),
+            slotted_args: HashMap::new(),
+            secondary_maps: vec![],
+        }
+    }
+
+    /// Opens a batch scope. Until the matching `end_batch` of the outermost
+    /// scope, written ids are recorded rather than propagated.
+    ///
+    /// There is no `Store::batch` taking a closure: reactions run against the
+    /// store, so they cannot run while it is borrowed for the closure. Use
+    /// `batch`, or `Runtime::batch` for a given store, instead.
+    pub fn begin_batch(&mut self) {
+        self.batch_depth += 1;
+    }
+
+    /// Closes a batch scope, returning the ids written during the batch once
+    /// the outermost scope exits. Nested scopes return an empty list.
+    pub fn end_batch(&mut self) -> Vec<StorageKey> {
+        self.batch_depth = self.batch_depth.saturating_sub(1);
+        if self.batch_depth == 0 {
+            std::mem::take(&mut self.batched_sources)
+        } else {
+            vec![]
         }
     }
 
+    pub fn is_batching(&self) -> bool {
+        self.batch_depth > 0
+    }
+
+    pub(crate) fn defer_propagation(&mut self, id: &StorageKey) {
+        if !self.batched_sources.contains(id) {
+            self.batched_sources.push(*id);
+        }
+    }
+
+    /// Defers re-running the reaction itself, e.g. because a node it observed
+    /// was disposed of, to the end of the batch.
+    pub(crate) fn defer_rerun(&mut self, id: &StorageKey) {
+        if !self.batched_reruns.contains(id) {
+            self.batched_reruns.push(*id);
+        }
+    }
+
+    /// The reactions to re-run once the outermost batch exits.
+    pub(crate) fn take_batched_reruns(&mut self) -> Vec<StorageKey> {
+        std::mem::take(&mut self.batched_reruns)
+    }
+
+    /// Inserts the node of a new subscription. Subscriptions have no
+    /// arguments, so they are numbered in a slotted location of their own
+    /// rather than taking an argument slot, and never reuse a key.
+    pub(crate) fn new_subscription(&mut self) -> StorageKey {
+        self.subscriptions += 1;
+        let id = StorageKey::SlottedKey(SlottedKey {
+            location: SUBSCRIPTION_LOCATION,
+            slot: self.subscriptions,
+            generation: 0,
+        });
+        self.insert_node(&id);
+        id
+    }
+
     pub fn new_reaction(&mut self, reaction_sm_key: &StorageKey, func: RxFunc) {
         let key = self.id_to_key_map.get(reaction_sm_key).unwrap().clone();
-        if let Some(map) = self.get_mut_secondarymap::<RxFunc>() {
-            map.insert(key, func);
-        } else {
-            let mut sm: SecondaryMap<DefaultKey, RxFunc> = SecondaryMap::new();
-            sm.insert(key, func);
-            self.anymap.insert(sm);
+        if self.get_secondarymap::<RxFunc>().is_none() {
+            self.register_secondarymap::<RxFunc>();
         }
+        self.get_mut_secondarymap::<RxFunc>()
+            .unwrap()
+            .insert(key, func);
     }
 
     fn responsive_map(&mut self) -> &mut SecondaryMap<DefaultKey, Vec<DefaultKey>> {
@@ -97,8 +352,14 @@ impl Store {
     }
 
     pub fn remove_dependency(&mut self, source_id: &StorageKey, reaction_id: &StorageKey) {
-        let source_sm_key = self.id_to_key_map.get(source_id).unwrap().clone();
-        let reaction_sm_key = self.id_to_key_map.get(reaction_id).unwrap().clone();
+        let (source_sm_key, reaction_sm_key) = match (
+            self.id_to_key_map.get(source_id),
+            self.id_to_key_map.get(reaction_id),
+        ) {
+            (Some(source_sm_key), Some(reaction_sm_key)) => (*source_sm_key, *reaction_sm_key),
+            // a disposed node has no edges left to remove
+            _ => return,
+        };
 
         let map = &mut self.responsive_map();
 
@@ -107,23 +368,343 @@ impl Store {
         } else {
             panic!("Trying to remove a from a state which does not exit")
         }
+        self.log(StoreEvent::DependencyRemoved {
+            source: *source_id,
+            dependent: *reaction_id,
+        });
     }
 
-    pub fn add_dependency(&mut self, source_id: &StorageKey, reaction_id: &StorageKey) {
-        let source_sm_key = self.id_to_key_map.get(source_id).unwrap().clone();
-        let reaction_sm_key = self.id_to_key_map.get(reaction_id).unwrap().clone();
+    /// Records that the reaction observes the source. Fails without adding
+    /// the edge if either node is unknown or if the source is itself
+    /// downstream of the reaction.
+    pub fn add_dependency(
+        &mut self,
+        source_id: &StorageKey,
+        reaction_id: &StorageKey,
+    ) -> Result<(), AtomicHooksError> {
+        let source_sm_key = *self
+            .id_to_key_map
+            .get(source_id)
+            .ok_or(AtomicHooksError::Missing { id: *source_id })?;
+        let reaction_sm_key = *self
+            .id_to_key_map
+            .get(reaction_id)
+            .ok_or(AtomicHooksError::Missing { id: *reaction_id })?;
+
+        if self.dependents_of(source_sm_key).contains(&reaction_sm_key) {
+            return Ok(());
+        }
+
+        if let Some(mut path) = self.dependency_path(reaction_sm_key, source_sm_key) {
+            path.push(reaction_sm_key);
+            return Err(DependencyCycle {
+                path: path.into_iter().map(|key| self.cycle_node(key)).collect(),
+            }
+            .into());
+        }
 
         let map = &mut self.responsive_map();
         if let Some(nodes) = map.get_mut(source_sm_key) {
-            if !nodes.contains(&reaction_sm_key) {
-                nodes.push(reaction_sm_key)
-            }
+            nodes.push(reaction_sm_key)
         } else {
             map.insert(source_sm_key, vec![reaction_sm_key]);
         }
+        self.log(StoreEvent::DependencyAdded {
+            source: *source_id,
+            dependent: *reaction_id,
+        });
+        Ok(())
+    }
+
+    /// Finds a chain of dependents leading from one node to another.
+    fn dependency_path(&self, from: DefaultKey, to: DefaultKey) -> Option<Vec<DefaultKey>> {
+        let mut parents: HashMap<DefaultKey, DefaultKey> = HashMap::new();
+        let mut worklist = VecDeque::new();
+        worklist.push_back(from);
+
+        while let Some(key) = worklist.pop_front() {
+            if key == to {
+                let mut path = vec![key];
+                let mut current = key;
+                while let Some(parent) = parents.get(&current) {
+                    path.push(*parent);
+                    current = *parent;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for dependent in self.dependents_of(key) {
+                if *dependent != from && !parents.contains_key(dependent) {
+                    parents.insert(*dependent, key);
+                    worklist.push_back(*dependent);
+                }
+            }
+        }
+        None
+    }
+
+    fn cycle_node(&self, key: DefaultKey) -> CycleNode {
+        CycleNode {
+            id: *self.primary_slotmap.get(key).unwrap(),
+            location: self
+                .get_secondarymap::<NodeLocation>()
+                .and_then(|map| map.get(key))
+                .map(|location| location.0.clone()),
+        }
+    }
+
+    pub(crate) fn set_location(&mut self, id: &StorageKey, location: String) {
+        self.set_state_with_id(NodeLocation(location), id);
+    }
+
+    pub(crate) fn set_node_kind(&mut self, id: &StorageKey, kind: NodeKind) {
+        self.set_state_with_id(kind, id);
+        self.log(StoreEvent::Created { id: *id, kind });
+    }
+
+    pub(crate) fn enable_event_log(&mut self, capacity: usize) {
+        self.event_log = Some(EventLog::new(capacity));
+    }
+
+    pub(crate) fn disable_event_log(&mut self) {
+        self.event_log = None;
+    }
+
+    pub(crate) fn event_log_enabled(&self) -> bool {
+        self.event_log.is_some()
+    }
+
+    pub(crate) fn clear_event_log(&mut self) {
+        if let Some(event_log) = &mut self.event_log {
+            event_log.clear();
+        }
+    }
+
+    pub(crate) fn logged_events(&self) -> impl Iterator<Item = &LoggedEvent> {
+        self.event_log
+            .iter()
+            .flat_map(|event_log| event_log.events())
+    }
+
+    /// Records the event if the event log is enabled.
+    pub(crate) fn log(&mut self, event: StoreEvent) {
+        if let Some(event_log) = &mut self.event_log {
+            event_log.push(event);
+        }
+    }
+
+    /// Whether writes of T to the node are recorded in the event log, which
+    /// they are for the value of atoms, reactions and hook states but not for
+    /// anything else stored alongside it.
+    pub(crate) fn logs_writes<T: Storable>(&self, id: &StorageKey) -> bool {
+        self.event_log_enabled()
+            && matches!(self.get_state_with_id::<ValueType>(id), Some(value_type) if value_type.id == TypeId::of::<T>())
+            && !matches!(
+                self.get_state_with_id::<NodeKind>(id),
+                Some(NodeKind::Subscription)
+            )
+    }
+
+    /// Renders a value of the node for the event log, if its type is `Debug`.
+    pub(crate) fn render<T: Storable>(&self, value: &T, id: &StorageKey) -> Option<String> {
+        self.get_state_with_id::<DebugRender<T>>(id)
+            .map(|debug_render| (debug_render.render)(value))
+    }
+
+    /// The renderer to log updates of the node with, `None` if they are not
+    /// logged.
+    pub(crate) fn update_logger<T: Storable>(
+        &self,
+        id: &StorageKey,
+    ) -> Option<Option<DebugRender<T>>> {
+        if self.logs_writes::<T>(id) {
+            Some(self.get_state_with_id::<DebugRender<T>>(id).copied())
+        } else {
+            None
+        }
+    }
+
+    /// Like `set_state_with_id`, but records the write in the event log and
+    /// queues it to be saved if the node is a persisted atom.
+    pub(crate) fn set_logged_state_with_id<T: Storable>(&mut self, data: T, id: &StorageKey) {
+        #[cfg(feature = "serde")]
+        self.persist(&data, id);
+        if !self.logs_writes::<T>(id) {
+            return self.set_state_with_id(data, id);
+        }
+        let old = self
+            .get_state_with_id::<T>(id)
+            .and_then(|old| self.render(old, id));
+        let new = self.render(&data, id);
+        self.set_state_with_id(data, id);
+        self.log(StoreEvent::Set { id: *id, old, new });
+    }
+
+    /// Marks a node as being constructed. Fails if the node is already being
+    /// constructed further up the stack, which means that its body
+    /// (transitively) observes itself.
+    pub(crate) fn begin_construction(&mut self, id: &StorageKey) -> Result<(), DependencyCycle> {
+        if let Some(pos) = self.under_construction.iter().position(|n| n == id) {
+            let mut path = self.under_construction[pos..].to_vec();
+            path.push(*id);
+            return Err(DependencyCycle {
+                path: path
+                    .into_iter()
+                    .map(|id| match self.id_to_key_map.get(&id) {
+                        Some(key) => self.cycle_node(*key),
+                        None => CycleNode { id, location: None },
+                    })
+                    .collect(),
+            });
+        }
+        self.under_construction.push(*id);
+        Ok(())
+    }
+
+    pub(crate) fn end_construction(&mut self, id: &StorageKey) {
+        self.under_construction.retain(|n| n != id);
+    }
+
+    /// Whether storing `data` for the id would leave its value unchanged.
+    /// Always false for nodes without a `ChangeCheck`.
+    pub(crate) fn write_is_unchanged<T: Storable>(&self, data: &T, id: &StorageKey) -> bool {
+        match (
+            self.get_state_with_id::<ChangeCheck<T>>(id),
+            self.get_state_with_id::<T>(id),
+        ) {
+            (Some(check), Some(previous)) => (check.unchanged)(previous, data),
+            _ => false,
+        }
+    }
+
+    /// Records that a reaction recomputed the value it already held.
+    pub(crate) fn mark_unchanged(&mut self, id: &StorageKey) {
+        self.unchanged_outputs.insert(*id);
+    }
+
+    pub(crate) fn take_unchanged(&mut self, id: &StorageKey) -> bool {
+        self.unchanged_outputs.remove(id)
+    }
+
+    /// Registers the reaction as lazy. It starts out dirty, as it has not been
+    /// evaluated yet.
+    pub(crate) fn make_lazy(&mut self, id: &StorageKey) {
+        self.lazy_reactions.insert(*id, true);
     }
 
-    pub(crate) fn state_exists_with_id<T: 'static>(&self, id: StorageKey) -> bool {
+    pub fn is_lazy(&self, id: &StorageKey) -> bool {
+        self.lazy_reactions.contains_key(id)
+    }
+
+    /// Marks a lazy reaction as needing to be re-evaluated on its next read.
+    /// Returns false, leaving the reaction alone, if it is not lazy.
+    pub(crate) fn mark_dirty(&mut self, id: &StorageKey) -> bool {
+        match self.lazy_reactions.get_mut(id) {
+            Some(dirty) => {
+                *dirty = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Clears the dirty flag of a lazy reaction, returning its function if it
+    /// needs to be re-evaluated.
+    pub(crate) fn take_dirty(&mut self, id: &StorageKey) -> Option<RxFunc> {
+        match self.lazy_reactions.get_mut(id) {
+            Some(dirty) if *dirty => {
+                *dirty = false;
+                self.get_state_with_id::<RxFunc>(id).cloned()
+            }
+            _ => None,
+        }
+    }
+
+    /// Starts a new generation of futures for the async reaction, returning
+    /// the generation together with the waker of the now stale future, if it
+    /// is waiting to be polled.
+    #[cfg(not(feature = "sync"))]
+    pub(crate) fn start_async_task(&mut self, id: &StorageKey) -> (u64, Option<std::task::Waker>) {
+        let task = self.async_tasks.entry(*id).or_default();
+        task.generation += 1;
+        (task.generation, task.waker.take())
+    }
+
+    #[cfg(not(feature = "sync"))]
+    pub(crate) fn is_current_async_task(&self, id: &StorageKey, generation: u64) -> bool {
+        self.async_tasks.get(id).map(|task| task.generation) == Some(generation)
+    }
+
+    /// Keeps the waker of a pending future, so that the future can be woken
+    /// and dropped once it is superseded.
+    #[cfg(not(feature = "sync"))]
+    pub(crate) fn park_async_task(&mut self, id: &StorageKey, waker: std::task::Waker) {
+        if let Some(task) = self.async_tasks.get_mut(id) {
+            task.waker = Some(waker);
+        }
+    }
+
+    #[cfg(not(feature = "sync"))]
+    pub(crate) fn cancel_async_task(&mut self, id: &StorageKey) -> Option<std::task::Waker> {
+        self.async_tasks.remove(id).and_then(|task| task.waker)
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn set_persist_backend(&mut self, backend: Arc<dyn crate::persist::PersistBackend>) {
+        self.persist_backend = Some(backend);
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn persist_backend(&self) -> Option<Arc<dyn crate::persist::PersistBackend>> {
+        self.persist_backend.clone()
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn set_persist_error_hook(&mut self, hook: Arc<dyn crate::persist::PersistErrorFn>) {
+        self.persist_error_hook = Some(hook);
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn persist_error_hook(&self) -> Option<Arc<dyn crate::persist::PersistErrorFn>> {
+        self.persist_error_hook.clone()
+    }
+
+    /// Queues a value of the node to be saved to the persist backend if the
+    /// node is a persisted atom. The backend is only called by
+    /// `save_pending_writes`, once the store is no longer borrowed.
+    #[cfg(feature = "serde")]
+    pub(crate) fn persist<T: Storable>(&mut self, value: &T, id: &StorageKey) {
+        let persisted_as = self
+            .get_state_with_id::<crate::persist::PersistedAs<T>>(id)
+            .copied();
+        if let (Some(persisted_as), Some(_)) = (persisted_as, &self.persist_backend) {
+            self.pending_saves.push(crate::persist::PendingSave {
+                id: *id,
+                key: persisted_as.key,
+                value: (persisted_as.encode)(value),
+            });
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn take_pending_saves(&mut self) -> Vec<crate::persist::PendingSave> {
+        std::mem::take(&mut self.pending_saves)
+    }
+
+    #[cfg(not(feature = "sync"))]
+    pub(crate) fn set_executor(&mut self, executor: Rc<dyn crate::loadable::Executor>) {
+        self.executor = Some(executor);
+    }
+
+    #[cfg(not(feature = "sync"))]
+    pub(crate) fn executor(&self) -> Option<Rc<dyn crate::loadable::Executor>> {
+        self.executor.clone()
+    }
+
+    pub(crate) fn state_exists_with_id<T: Storable>(&self, id: StorageKey) -> bool {
+        if self.checked_out.contains(&(id, TypeId::of::<T>())) {
+            return true;
+        }
         match (self.id_to_key_map.get(&id), self.get_secondarymap::<T>()) {
             (Some(existing_key), Some(existing_secondary_map)) => {
                 existing_secondary_map.contains_key(*existing_key)
@@ -132,7 +713,7 @@ impl Store {
         }
     }
 
-    pub fn get_state_with_id<T: 'static>(&self, current_id: &StorageKey) -> Option<&T> {
+    pub fn get_state_with_id<T: Storable>(&self, current_id: &StorageKey) -> Option<&T> {
         match (
             self.id_to_key_map.get(current_id),
             self.get_secondarymap::<T>(),
@@ -144,7 +725,68 @@ impl Store {
         }
     }
 
-    pub(crate) fn remove_state_with_id<T: 'static>(
+    pub(crate) fn record_value_type<T: Storable>(&mut self, id: &StorageKey) {
+        self.set_state_with_id(
+            ValueType {
+                id: TypeId::of::<T>(),
+                name: std::any::type_name::<T>(),
+            },
+            id,
+        );
+    }
+
+    /// Explains why no state of type T can be found for the id.
+    pub(crate) fn missing_state_error<T: Storable>(&self, id: &StorageKey) -> AtomicHooksError {
+        self.check_writable::<T>(id)
+            .err()
+            .unwrap_or(AtomicHooksError::Missing { id: *id })
+    }
+
+    /// Checks that a value of type T may be stored for the id.
+    pub(crate) fn check_writable<T: Storable>(
+        &self,
+        id: &StorageKey,
+    ) -> Result<(), AtomicHooksError> {
+        if self.checked_out.contains(&(*id, TypeId::of::<T>())) {
+            return Err(AtomicHooksError::ReentrantBorrow { id: *id });
+        }
+        match self.get_state_with_id::<ValueType>(id) {
+            Some(value_type) if value_type.id != TypeId::of::<T>() => {
+                Err(AtomicHooksError::WrongType {
+                    id: *id,
+                    expected: std::any::type_name::<T>(),
+                    found: value_type.name,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes the state out of the store while it is being read or updated,
+    /// so that the store stays accessible in the meantime. Must be paired
+    /// with `check_in`.
+    pub(crate) fn check_out<T: Storable>(
+        &mut self,
+        id: &StorageKey,
+    ) -> Result<T, AtomicHooksError> {
+        match self.remove_state_with_id::<T>(id) {
+            Some(item) => {
+                self.checked_out.insert((*id, TypeId::of::<T>()));
+                Ok(item)
+            }
+            None => Err(self.missing_state_error::<T>(id)),
+        }
+    }
+
+    /// Puts checked out state back, unless the node was disposed of in the
+    /// meantime.
+    pub(crate) fn check_in<T: Storable>(&mut self, item: T, id: &StorageKey) {
+        if self.checked_out.remove(&(*id, TypeId::of::<T>())) {
+            self.set_state_with_id(item, id);
+        }
+    }
+
+    pub(crate) fn remove_state_with_id<T: Storable>(
         &mut self,
         current_id: &StorageKey,
     ) -> Option<T> {
@@ -166,30 +808,166 @@ impl Store {
         }
     }
 
-    pub(crate) fn clone_dep_funcs_for_id(&mut self, id: &StorageKey) -> Vec<(StorageKey, RxFunc)> {
-        let reaction_keys = self.get_state_with_id::<Vec<DefaultKey>>(id).cloned();
+    fn dependents_of(&self, key: DefaultKey) -> &[DefaultKey] {
+        self.get_secondarymap::<Vec<DefaultKey>>()
+            .and_then(|map| map.get(key))
+            .map(|nodes| nodes.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn dependency_graph(&self) -> DependencyGraph {
+        let mut nodes = self
+            .primary_slotmap
+            .iter()
+            .map(|(key, id)| {
+                let kind = match (id, self.get_state_with_id::<NodeKind>(id)) {
+                    (_, Some(kind)) => *kind,
+                    (StorageKey::TopoKey(_), None) => NodeKind::HookState,
+                    (StorageKey::SlottedKey(_), None) => NodeKind::Atom,
+                };
+                let node = GraphNode {
+                    id: *id,
+                    kind,
+                    value_type: self
+                        .get_state_with_id::<ValueType>(id)
+                        .map(|value_type| value_type.name),
+                    location: self
+                        .get_state_with_id::<NodeLocation>(id)
+                        .map(|location| location.0.clone()),
+                };
+                (key, node)
+            })
+            .collect::<Vec<_>>();
+        nodes.sort_by(|(_, a), (_, b)| {
+            (&a.location, a.value_type, a.kind.name()).cmp(&(
+                &b.location,
+                b.value_type,
+                b.kind.name(),
+            ))
+        });
+
+        let index = nodes
+            .iter()
+            .enumerate()
+            .map(|(idx, (key, _))| (*key, idx))
+            .collect::<HashMap<_, _>>();
+        let edges = nodes
+            .iter()
+            .flat_map(|(key, _)| {
+                let from = index[key];
+                self.dependents_of(*key)
+                    .iter()
+                    .filter_map(|dependent| index.get(dependent))
+                    .map(move |to| (from, *to))
+            })
+            .collect();
+
+        DependencyGraph {
+            nodes: nodes.into_iter().map(|(_, node)| node).collect(),
+            edges,
+        }
+    }
 
-        if let Some(reaction_keys) = &reaction_keys {
-            reaction_keys
+    pub(crate) fn dependent_ids(&self, id: &StorageKey) -> Vec<StorageKey> {
+        match self.id_to_key_map.get(id) {
+            Some(key) => self
+                .dependents_of(*key)
                 .iter()
-                .filter_map(|key| {
-                    if let Some(existing_secondary_map) = self.get_mut_secondarymap::<RxFunc>() {
-                        if let Some(reaction) = existing_secondary_map.get(*key).cloned() {
-                            Some((self.primary_slotmap.get(*key).unwrap().clone(), reaction))
-                        } else {
-                            panic!("cannot find {:#?} for id {:#?}", key, id);
-                        }
-                    } else {
-                        None
-                    }
-                })
-                .collect::<Vec<(StorageKey, RxFunc)>>()
-        } else {
-            vec![]
+                .filter_map(|dependent| self.primary_slotmap.get(*dependent).copied())
+                .collect(),
+            None => vec![],
         }
     }
 
-    pub(crate) fn set_state_with_id<T: 'static>(&mut self, data: T, current_id: &StorageKey) {
+    /// Collects every reaction downstream of the given sources and returns
+    /// them ordered by dependency height, i.e. a reaction only appears after
+    /// every affected reaction it observes. Each reaction appears exactly
+    /// once. The walk uses an explicit worklist so that long dependency
+    /// chains do not grow the call stack.
+    pub(crate) fn reactions_in_height_order(
+        &self,
+        sources: &[StorageKey],
+    ) -> Vec<(StorageKey, RxFunc)> {
+        self.reactions_in_height_order_including(sources, &[])
+    }
+
+    /// Like `reactions_in_height_order`, but also orders the given reactions
+    /// themselves together with the reactions downstream of them.
+    pub(crate) fn reactions_in_height_order_including(
+        &self,
+        sources: &[StorageKey],
+        reactions: &[StorageKey],
+    ) -> Vec<(StorageKey, RxFunc)> {
+        let mut source_keys = vec![];
+        let mut included_keys = vec![];
+        for id in sources.iter().chain(reactions) {
+            if let Some(key) = self.id_to_key_map.get(id) {
+                if !source_keys.contains(key) {
+                    source_keys.push(*key);
+                }
+            }
+        }
+        for id in reactions {
+            if let Some(key) = self.id_to_key_map.get(id) {
+                included_keys.push(*key);
+            }
+        }
+
+        // first pass: find the affected sub-graph and count incoming edges
+        let mut in_degree: HashMap<DefaultKey, usize> = HashMap::new();
+        let mut visited: HashSet<DefaultKey> = HashSet::new();
+        let mut worklist = source_keys.clone();
+        while let Some(key) = worklist.pop() {
+            if !visited.insert(key) {
+                continue;
+            }
+            for dependent in self.dependents_of(key) {
+                *in_degree.entry(*dependent).or_insert(0) += 1;
+                worklist.push(*dependent);
+            }
+        }
+
+        // second pass: Kahn's algorithm, recording the height of each node
+        let mut heights: HashMap<DefaultKey, usize> = HashMap::new();
+        let mut ready = source_keys
+            .iter()
+            .filter(|key| !in_degree.contains_key(key))
+            .copied()
+            .collect::<VecDeque<_>>();
+        let mut ordered = vec![];
+
+        while let Some(key) = ready.pop_front() {
+            let height = heights.get(&key).copied().unwrap_or(0);
+            if !source_keys.contains(&key) || included_keys.contains(&key) {
+                ordered.push((height, key));
+            }
+            for dependent in self.dependents_of(key) {
+                let dependent_height = heights.entry(*dependent).or_insert(0);
+                *dependent_height = (*dependent_height).max(height + 1);
+                let remaining = in_degree.get_mut(dependent).unwrap();
+                *remaining -= 1;
+                if *remaining == 0 {
+                    ready.push_back(*dependent);
+                }
+            }
+        }
+
+        ordered.sort_by_key(|(height, _)| *height);
+
+        let reactions = self.get_secondarymap::<RxFunc>();
+        ordered
+            .into_iter()
+            .map(|(_, key)| {
+                let id = self.primary_slotmap.get(key).copied().unwrap();
+                match reactions.and_then(|map| map.get(key)) {
+                    Some(reaction) => (id, reaction.clone()),
+                    None => panic!("cannot find {:#?} for id {:#?}", key, id),
+                }
+            })
+            .collect()
+    }
+
+    pub(crate) fn set_state_with_id<T: Storable>(&mut self, data: T, current_id: &StorageKey) {
         //unwrap or default to keep borrow checker happy
         let key = self
             .id_to_key_map
@@ -214,61 +992,135 @@ impl Store {
         }
     }
 
-    pub fn get_secondarymap<T: 'static>(&self) -> Option<&SecondaryMap<DefaultKey, T>> {
+    pub fn get_secondarymap<T: Storable>(&self) -> Option<&SecondaryMap<DefaultKey, T>> {
         self.anymap.get::<SecondaryMap<DefaultKey, T>>()
     }
 
-    pub fn get_mut_secondarymap<T: 'static>(&mut self) -> Option<&mut SecondaryMap<DefaultKey, T>> {
+    pub fn get_mut_secondarymap<T: Storable>(
+        &mut self,
+    ) -> Option<&mut SecondaryMap<DefaultKey, T>> {
         self.anymap.get_mut::<SecondaryMap<DefaultKey, T>>()
     }
 
-    pub fn register_secondarymap<T: 'static>(&mut self) {
+    pub fn register_secondarymap<T: Storable>(&mut self) {
         let sm: SecondaryMap<DefaultKey, T> = SecondaryMap::new();
-        self.anymap.insert(sm);
+        if self.anymap.insert(sm).is_none() {
+            self.secondary_maps.push(SecondaryMapOps::of::<T>());
+        }
     }
 
-    pub fn return_key_for_type_and_insert_if_required<T: 'static + Clone + Eq + Hash>(
+    /// Gives the id a slot in the primary slotmap if it does not have one.
+    pub(crate) fn insert_node(&mut self, id: &StorageKey) -> DefaultKey {
+        match self.id_to_key_map.get(id) {
+            Some(key) => *key,
+            None => {
+                let key = self.primary_slotmap.insert(*id);
+                self.id_to_key_map.insert(*id, key);
+                key
+            }
+        }
+    }
+
+    /// Removes a node together with everything stored for it: its state and
+    /// every other value keyed to it, its dependents list, the edges from the
+    /// nodes it observes and its slot in the primary slotmap.
+    pub fn dispose(&mut self, id: &StorageKey) {
+        let key = match self.id_to_key_map.remove(id) {
+            Some(key) => key,
+            None => return,
+        };
+        self.log(StoreEvent::Disposed { id: *id });
+        #[cfg(feature = "serde")]
+        self.snapshots.forget(id);
+
+        for ops in self.secondary_maps.clone() {
+            (ops.remove)(&mut self.anymap, key);
+        }
+        if let Some(map) = self.get_mut_secondarymap::<Vec<DefaultKey>>() {
+            for (_, dependents) in map.iter_mut() {
+                dependents.retain(|dependent| *dependent != key);
+            }
+        }
+        self.primary_slotmap.remove(key);
+
+        self.lazy_reactions.remove(id);
+        self.unchanged_outputs.remove(id);
+        self.batched_sources.retain(|source| source != id);
+        self.checked_out
+            .retain(|(checked_out, _)| checked_out != id);
+        if let StorageKey::SlottedKey(SlottedKey { location, slot, .. }) = id {
+            if let Some(arg_slot) = self
+                .slotted_args
+                .get_mut(location)
+                .and_then(|slots| slots.get_mut(*slot as usize))
+            {
+                arg_slot.args = None;
+                arg_slot.generation += 1;
+            }
+        }
+    }
+
+    /// Number of entries held by the store, used to check that disposed nodes
+    /// leave nothing behind.
+    pub fn size(&self) -> usize {
+        let values = self
+            .secondary_maps
+            .iter()
+            .map(|ops| (ops.len)(&self.anymap))
+            .sum::<usize>();
+        let args = self
+            .slotted_args
+            .values()
+            .flatten()
+            .filter(|arg_slot| arg_slot.args.is_some())
+            .count();
+        self.primary_slotmap.len() + self.id_to_key_map.len() + values + args
+    }
+
+    #[cfg(test)]
+    pub(crate) fn slotted_locations(&self) -> usize {
+        self.slotted_args.len()
+    }
+
+    /// Returns the key for the argument tuple hashed to `location`. Tuples
+    /// are given the first free slot at their location, so tuples whose
+    /// hashes collide are told apart by comparing them with the tuples that
+    /// already hold a slot there. The slot of a disposed node is reused with
+    /// the next generation.
+    pub fn return_key_for_type_and_insert_if_required<T: Storable + Clone + Eq + Hash>(
         &mut self,
-        id: StorageKey,
+        location: u64,
         value: T,
     ) -> StorageKey {
-        //unwrap or default to keep borrow checker happy
-        let key = self.id_to_key_map.get(&id).copied().unwrap_or_default();
-
-        if key.is_null() {
-            let key = self.primary_slotmap.insert(id);
-            self.id_to_key_map.insert(id, key);
-            if let Some(sec_map) = self.get_mut_secondarymap::<T>() {
-                if let Some(item) = sec_map.get(key) {
-                    if item == &value {
-                        id
-                    } else {
-                        unimplemented!() // deeper check needed here;
+        let slots = self.slotted_args.entry(location).or_default();
+        let existing = slots.iter().position(|arg_slot| {
+            arg_slot
+                .args
+                .as_ref()
+                .and_then(|args| args.downcast_ref::<T>())
+                == Some(&value)
+        });
+        let slot = match existing {
+            Some(slot) => slot,
+            None => {
+                let slot = match slots.iter().position(|arg_slot| arg_slot.args.is_none()) {
+                    Some(slot) => slot,
+                    None => {
+                        slots.push(ArgSlot::default());
+                        slots.len() - 1
                     }
-                } else {
-                    sec_map.insert(key, value);
-                    id
-                }
-            } else {
-                self.register_secondarymap::<T>();
-                self.get_mut_secondarymap::<T>().unwrap().insert(key, value);
-                id
+                };
+                slots[slot].args = Some(Box::new(value));
+                slot
             }
-        } else if let Some(existing_secondary_map) = self.get_mut_secondarymap::<T>() {
-            if let Some(item) = existing_secondary_map.get(key) {
-                if item == &value {
-                    id
-                } else {
-                    unimplemented!() // deeper check needed here;
-                }
-            } else {
-                existing_secondary_map.insert(key, value);
-                id
-            }
-        } else {
-            self.register_secondarymap::<T>();
-            self.get_mut_secondarymap::<T>().unwrap().insert(key, value);
-            id
-        }
+        };
+
+        let id = StorageKey::SlottedKey(SlottedKey {
+            location,
+            slot: slot as u64,
+            generation: slots[slot].generation,
+        });
+        self.insert_node(&id);
+        id
     }
 }
@@ -1,12 +1,15 @@
-use crate::reactive_state_access::state_access::StateAccess;
+use crate::{reactive_state_access::state_access::StateAccess, store::StorableFn};
 
 pub struct Unmount {
     pub activated: bool,
+    #[cfg(not(feature = "sync"))]
     pub on_unmount: Box<dyn Fn() -> ()>,
+    #[cfg(feature = "sync")]
+    pub on_unmount: Box<dyn Fn() -> () + Send + Sync>,
 }
 
 impl Unmount {
-    pub fn new(on_unmount: impl Fn() -> () + 'static) -> Self {
+    pub fn new(on_unmount: impl StorableFn) -> Self {
         Self {
             activated: true,
             on_unmount: Box::new(on_unmount),